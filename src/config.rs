@@ -0,0 +1,495 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{anyhow, Result};
+use clap::{ArgMatches, Command};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+/// Settings holding a credential, redacted when the effective config is printed
+pub const SECRET_SETTINGS: &[&str] = &["hubitat_api_access_token", "hubitat_auth_pwd"];
+
+/// Flags holding a credential and the flag holding the file of the same credential, by argument ID
+pub const CREDENTIAL_FLAGS: &[(&str, &str)] = &[("he_api_token", "he_api_token_file"), ("he_auth_pwd", "he_auth_pwd_file")];
+
+/// Sections of the config file that are not flags, `mapping`, `unit` and `stale` are read by `mapping::Mappings`
+const SECTIONS: &[&str] = &["hub", "module", "devices", "mapping", "unit", "stale"];
+
+/// Hub scraped by the exporter, every series of the hub carries its `hub` label and extra `labels`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct HubConfig {
+  pub name:           String,
+  pub ip:             String,
+  pub app_id:         String,
+  #[serde(default)]
+  pub api_token:      String,
+  /// File holding the API token, re-read on every poll
+  #[serde(default)]
+  pub api_token_file: Option<String>,
+  #[serde(default)]
+  pub auth_usr:       Option<String>,
+  #[serde(default)]
+  pub auth_pwd:       Option<String>,
+  /// File holding the password, re-read on every poll
+  #[serde(default)]
+  pub auth_pwd_file:  Option<String>,
+  /// Log into the hub for the device inventory and hub metrics
+  #[serde(default)]
+  pub details:        bool,
+  #[serde(default)]
+  pub labels:         BTreeMap<String, String>,
+}
+
+/// Credentials and settings of the hubs probed with `/probe?target=<ip>&module=<name>`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct ModuleConfig {
+  pub app_id:         String,
+  #[serde(default)]
+  pub api_token:      String,
+  /// File holding the API token, re-read on every poll
+  #[serde(default)]
+  pub api_token_file: Option<String>,
+  #[serde(default)]
+  pub auth_usr:       Option<String>,
+  #[serde(default)]
+  pub auth_pwd:       Option<String>,
+  /// File holding the password, re-read on every poll
+  #[serde(default)]
+  pub auth_pwd_file:  Option<String>,
+  #[serde(default)]
+  pub details:        bool,
+  #[serde(default)]
+  pub labels:         BTreeMap<String, String>,
+}
+
+impl ModuleConfig {
+  /// Returns the hub at `target` described by this module, the target is also the hub name
+  pub fn hub(&self, target: &str) -> HubConfig { HubConfig { name: target.to_string(), ip: target.to_string(), app_id: self.app_id.clone(), api_token: self.api_token.clone(), api_token_file: self.api_token_file.clone(), auth_usr: self.auth_usr.clone(), auth_pwd: self.auth_pwd.clone(), auth_pwd_file: self.auth_pwd_file.clone(), details: self.details, labels: self.labels.clone() } }
+}
+
+/// Devices to expose, matched on their ID, label, name or driver with `*` wildcards. An empty `include` keeps all the devices.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct DeviceFilter {
+  #[serde(default)]
+  pub include: Vec<String>,
+  #[serde(default)]
+  pub exclude: Vec<String>,
+}
+
+impl DeviceFilter {
+  pub fn is_included(&self, id: &str, label: &str, name: &str, driver: &str) -> bool {
+    let matches = |patterns: &[String]| patterns.iter().any(|p| [id, label, name, driver].iter().any(|v| wildcard_match(p, v)));
+    (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+  }
+}
+
+/// Case insensitive match of `value` against `pattern`, where `*` matches any sequence of characters
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+  let (pattern, value) = (pattern.to_lowercase(), value.to_lowercase());
+  let mut parts = pattern.split('*');
+  let first = parts.next().unwrap_or_default();
+  let Some(mut rest) = value.strip_prefix(first) else {
+    return false;
+  };
+  if !pattern.contains('*') {
+    return rest.is_empty();
+  }
+
+  let parts: Vec<&str> = parts.collect();
+  for (i, part) in parts.iter().enumerate() {
+    if i == parts.len() - 1 {
+      return rest.ends_with(part);
+    }
+    match rest.find(part) {
+      Some(idx) => rest = &rest[idx + part.len()..],
+      None => return false,
+    }
+  }
+  true
+}
+
+/// Config file, in TOML or in YAML when the file has a `.yaml` or `.yml` extension.
+/// The top level keys are the long names of the command line flags, they are overridden by the flags and the environment.
+#[derive(Debug, Deserialize, Default)]
+pub struct Config {
+  #[serde(default)]
+  pub hub:      Vec<HubConfig>,
+  #[serde(default)]
+  pub module:   BTreeMap<String, ModuleConfig>,
+  #[serde(default)]
+  pub devices:  DeviceFilter,
+  #[serde(flatten)]
+  pub settings: BTreeMap<String, Value>,
+}
+
+/// Parses a TOML or YAML file depending on its extension
+pub fn parse_file<T: DeserializeOwned>(path: &Path) -> Result<T> {
+  let content = fs::read_to_string(path).map_err(|e| anyhow!("reading {:?} failed: {:?}", path, e))?;
+  match path.extension().and_then(|e| e.to_str()) {
+    Some("yaml" | "yml") => serde_yaml::from_str(&content).map_err(|e| anyhow!("parsing {:?} failed: {}", path, e)),
+    _ => toml::from_str(&content).map_err(|e| anyhow!("parsing {:?} failed: {}", path, e)),
+  }
+}
+
+/// Returns the config file given with `--config` or `CONFIG_FILE`, looked up before the flags are parsed as the file provides their defaults
+pub fn path_from_args() -> Option<String> {
+  let mut args = std::env::args().skip(1);
+  while let Some(a) = args.next() {
+    if a == "--config" {
+      return args.next();
+    }
+    if let Some(p) = a.strip_prefix("--config=") {
+      return Some(p.to_string());
+    }
+  }
+  std::env::var("CONFIG_FILE").ok()
+}
+
+impl Config {
+  pub fn load(path: &str) -> Result<Config> {
+    let mut config: Config = parse_file(Path::new(path))?;
+    config.settings.retain(|k, _| !SECTIONS.contains(&k.as_str()));
+    config.validate()?;
+
+    Ok(config)
+  }
+
+  /// Sets the settings of the file as the defaults of the matching flags, so the flags and the environment take precedence over the file
+  pub fn apply(&self, mut cmd: Command) -> Result<Command> {
+    for (key, value) in self.settings.iter() {
+      let Some(id) = cmd.get_arguments().find(|a| a.get_long() == Some(key.as_str()) && key != "config").map(|a| a.get_id().clone()) else {
+        return Err(anyhow!("unknown setting {:?} in config file", key));
+      };
+      let values: Vec<String> = match value {
+        Value::Array(a) => a.iter().map(setting_value).collect::<Result<_>>()?,
+        v => vec![setting_value(v)?],
+      };
+      cmd = cmd.mut_arg(id, |a| a.default_values(values));
+    }
+    if let Some((v, f)) = CREDENTIAL_FLAGS.iter().find(|(v, f)| self.settings.contains_key(long_name(&cmd, v)) && self.settings.contains_key(long_name(&cmd, f))) {
+      return Err(anyhow!("config file sets both the {:?} and the {:?} settings", long_name(&cmd, v), long_name(&cmd, f)));
+    }
+
+    Ok(cmd)
+  }
+
+  /// Prints the effective config, every flag with its resolved value, the config file sections and the loaded mappings, with the credentials redacted
+  pub fn effective(&self, cmd: &Command, app: &ArgMatches, hubs: &[HubConfig], mappings: &crate::mapping::Mappings) -> Result<String> {
+    let redacted = |v: &mut Option<String>| {
+      if v.is_some() {
+        *v = Some("<redacted>".to_string());
+      }
+    };
+
+    let mut out = toml::Table::new();
+    for a in cmd.get_arguments().filter(|a| !matches!(a.get_id().as_str(), "config" | "check_config")) {
+      let (Some(long), Some(raw)) = (a.get_long(), app.get_raw(a.get_id().as_str())) else {
+        continue;
+      };
+      if is_overridden(app, a.get_id().as_str()) {
+        continue;
+      }
+      let values: Vec<String> = raw.map(|v| v.to_string_lossy().into_owned()).collect();
+      let value = if SECRET_SETTINGS.contains(&long) {
+        toml::Value::String("<redacted>".to_string())
+      } else if matches!(a.get_action(), clap::ArgAction::SetTrue) {
+        toml::Value::Boolean(app.get_flag(a.get_id().as_str()))
+      } else if let Ok(Some(n)) = app.try_get_one::<u64>(a.get_id().as_str()) {
+        toml::Value::Integer(*n as i64)
+      } else if let Ok(Some(n)) = app.try_get_one::<usize>(a.get_id().as_str()) {
+        toml::Value::Integer(*n as i64)
+      } else if let Ok(Some(n)) = app.try_get_one::<u8>(a.get_id().as_str()) {
+        toml::Value::Integer(*n as i64)
+      } else if matches!(a.get_action(), clap::ArgAction::Append) {
+        toml::Value::Array(values.into_iter().map(toml::Value::String).collect())
+      } else {
+        toml::Value::String(values.join(","))
+      };
+      out.insert(long.to_string(), value);
+    }
+
+    let mut hubs = hubs.to_vec();
+    let mut modules = self.module.clone();
+    for h in hubs.iter_mut() {
+      h.api_token = if h.api_token.is_empty() { String::new() } else { "<redacted>".to_string() };
+      redacted(&mut h.auth_pwd);
+    }
+    for m in modules.values_mut() {
+      m.api_token = if m.api_token.is_empty() { String::new() } else { "<redacted>".to_string() };
+      redacted(&mut m.auth_pwd);
+    }
+    out.insert("devices".to_string(), toml::Value::try_from(&self.devices)?);
+    out.insert("module".to_string(), toml::Value::try_from(&modules)?);
+    out.insert("hub".to_string(), toml::Value::try_from(&hubs)?);
+    out.insert("mapping".to_string(), toml::Value::try_from(&mappings.mappings)?);
+    out.insert("unit".to_string(), toml::Value::try_from(&mappings.units)?);
+    out.insert("stale".to_string(), toml::Value::try_from(&mappings.stale)?);
+
+    toml::to_string(&out).map_err(|e| anyhow!("printing the config failed: {}", e))
+  }
+
+  fn validate(&self) -> Result<()> {
+    check_hub_names(&self.hub)?;
+    for (i, h) in self.hub.iter().enumerate() {
+      if h.name.is_empty() {
+        return Err(anyhow!("hub {} has no name", i));
+      }
+      if h.api_token.is_empty() == h.api_token_file.is_none() {
+        return Err(anyhow!("hub {:?} needs either an api_token or an api_token_file", h.name));
+      }
+      if (h.auth_pwd.is_some() || h.auth_pwd_file.is_some()) && h.auth_usr.is_none() {
+        return Err(anyhow!("hub {:?} has a password without a user", h.name));
+      }
+      if let Some(l) = h.labels.keys().find(|l| l.as_str() == "hub" || !is_valid_label_name(l)) {
+        return Err(anyhow!("hub {:?} has an invalid label name {:?}", h.name, l));
+      }
+    }
+    for (name, m) in self.module.iter() {
+      if m.api_token.is_empty() == m.api_token_file.is_none() {
+        return Err(anyhow!("module {:?} needs either an api_token or an api_token_file", name));
+      }
+      if (m.auth_pwd.is_some() || m.auth_pwd_file.is_some()) && m.auth_usr.is_none() {
+        return Err(anyhow!("module {:?} has a password without a user", name));
+      }
+      if let Some(l) = m.labels.keys().find(|l| l.as_str() == "hub" || !is_valid_label_name(l)) {
+        return Err(anyhow!("module {:?} has an invalid label name {:?}", name, l));
+      }
+    }
+
+    Ok(())
+  }
+}
+
+/// Returns the long flag name of an argument ID
+fn long_name<'a>(cmd: &'a Command, id: &str) -> &'a str { cmd.get_arguments().find(|a| a.get_id() == id).and_then(|a| a.get_long()).unwrap_or_default() }
+
+/// Whether the credential flag `id` is overridden by the other flag of its `CREDENTIAL_FLAGS` pair given from a higher precedence source,
+/// for instance a file setting by a command line token. `conflicts_with` does not apply to the defaults set from the config file.
+pub fn is_overridden(app: &ArgMatches, id: &str) -> bool {
+  let other = CREDENTIAL_FLAGS.iter().find_map(|(v, f)| {
+                                       if *v == id {
+                                         Some(f)
+                                       } else if *f == id {
+                                         Some(v)
+                                       } else {
+                                         None
+                                       }
+                                     });
+  other.is_some_and(|o| app.value_source(o) > app.value_source(id))
+}
+
+/// Returns the value of the credential flag `id`, unless it is overridden by the other flag of its pair
+pub fn credential(app: &ArgMatches, id: &str) -> Option<String> {
+  if is_overridden(app, id) {
+    None
+  } else {
+    app.get_one::<String>(id).cloned()
+  }
+}
+
+/// Checks that every hub has its own name, the name is the `hub` label of its series
+pub fn check_hub_names(hubs: &[HubConfig]) -> Result<()> {
+  for (i, h) in hubs.iter().enumerate() {
+    if hubs[..i].iter().any(|o| o.name == h.name) {
+      return Err(anyhow!("hub name {:?} is used more than once", h.name));
+    }
+  }
+
+  Ok(())
+}
+
+/// Converts a scalar setting of the config file to its flag value
+fn setting_value(v: &Value) -> Result<String> {
+  match v {
+    Value::String(s) => Ok(s.clone()),
+    Value::Number(n) => Ok(n.to_string()),
+    Value::Bool(b) => Ok(b.to_string()),
+    v => Err(anyhow!("invalid setting value {}", v)),
+  }
+}
+
+/// Checks that `name` can be used as a label name
+pub fn is_valid_label_name(name: &str) -> bool { !name.is_empty() && !name.starts_with("__") && name.chars().enumerate().all(|(i, c)| c.is_ascii_alphabetic() || c == '_' || (i > 0 && c.is_ascii_digit())) }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn config(settings: &[(&str, Value)]) -> Config {
+    Config { settings: settings.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+             ..Default::default() }
+  }
+
+  fn matches(config: &Config, args: &[&str]) -> ArgMatches { config.apply(crate::cli()).unwrap().try_get_matches_from(["hubitat_exporter", "--config", "c.toml"].iter().chain(args)).unwrap() }
+
+  fn hub(name: &str) -> HubConfig {
+    HubConfig { name: name.to_string(),
+                ip: "192.168.1.2".to_string(),
+                app_id: "1".to_string(),
+                api_token: "tok".to_string(),
+                ..Default::default() }
+  }
+
+  #[test]
+  fn wildcard_match_patterns() {
+    assert!(wildcard_match("Kitchen Light", "kitchen light"));
+    assert!(!wildcard_match("Kitchen", "Kitchen Light"));
+    assert!(wildcard_match("*", ""));
+    assert!(wildcard_match("Relay*", "Relay channel 1"));
+    assert!(wildcard_match("*Sensor", "Generic Zigbee Temp Sensor"));
+    assert!(wildcard_match("Generic*Temp*", "Generic Zigbee Temp Sensor"));
+    assert!(wildcard_match("a*b*c", "abc"));
+    assert!(!wildcard_match("a*b*c", "acb"));
+    assert!(!wildcard_match("*Sensor", "Sensor Hub"));
+    assert!(!wildcard_match("aa*aa", "aaa"));
+  }
+
+  #[test]
+  fn device_filter_include_exclude() {
+    let filter = DeviceFilter { include: vec!["Generic*".to_string(), "42".to_string()], exclude: vec!["*Relay*".to_string()] };
+    assert!(filter.is_included("1", "Outlet", "Outlet", "Generic Zigbee Outlet"));
+    assert!(filter.is_included("42", "Lamp", "Lamp", "Hue Bulb"));
+    assert!(!filter.is_included("7", "Lamp", "Lamp", "Hue Bulb"));
+    assert!(!filter.is_included("3", "Relay channel 1", "Relay EP1", "Generic Component Switch"));
+    assert!(DeviceFilter::default().is_included("7", "Lamp", "Lamp", "Hue Bulb"));
+  }
+
+  #[test]
+  fn validate_hubs() {
+    assert!(Config { hub: vec![hub("main"), hub("garage")],
+                     ..Default::default() }.validate()
+                                           .is_ok());
+
+    let invalid = [(hub(""), "has no name"),
+                   (HubConfig { api_token: String::new(),
+                                ..hub("main") },
+                    "needs either an api_token"),
+                   (HubConfig { api_token_file: Some("/run/secrets/token".to_string()),
+                                ..hub("main") },
+                    "needs either an api_token"),
+                   (HubConfig { auth_pwd: Some("pwd".to_string()),
+                                ..hub("main") },
+                    "password without a user"),
+                   (HubConfig { labels: [("hub".to_string(), "x".to_string())].into(),
+                                ..hub("main") },
+                    "invalid label name"),
+                   (HubConfig { labels: [("1site".to_string(), "x".to_string())].into(),
+                                ..hub("main") },
+                    "invalid label name")];
+    for (h, err) in invalid {
+      let e = Config { hub: vec![h],
+                       ..Default::default() }.validate()
+                                             .unwrap_err()
+                                             .to_string();
+      assert!(e.contains(err), "{e}");
+    }
+
+    let e = Config { hub: vec![hub("main"), hub("main")],
+                     ..Default::default() }.validate()
+                                           .unwrap_err()
+                                           .to_string();
+    assert!(e.contains("used more than once"), "{e}");
+
+    // The hub given on the command line is named after its IP
+    let e = check_hub_names(&[hub("192.168.1.2"), hub("main"), hub("192.168.1.2")]).unwrap_err().to_string();
+    assert!(e.contains("\"192.168.1.2\" is used more than once"), "{e}");
+  }
+
+  #[test]
+  fn validate_modules() {
+    let module = ModuleConfig { app_id: "1".to_string(),
+                                api_token: "tok".to_string(),
+                                ..Default::default() };
+    assert!(Config { module: [("main".to_string(), module.clone())].into(),
+                     ..Default::default() }.validate()
+                                           .is_ok());
+
+    let e = Config { module: [("main".to_string(),
+                               ModuleConfig { auth_pwd_file: Some("/run/secrets/pwd".to_string()),
+                                              ..module })].into(),
+                     ..Default::default() }.validate()
+                                           .unwrap_err()
+                                           .to_string();
+    assert!(e.contains("password without a user"), "{e}");
+  }
+
+  #[test]
+  fn apply_file_settings_as_defaults() {
+    let config = config(&[("hubitat_poll_interval", 60.into()), ("info_attributes", serde_json::json!(["firmware", "lastCheckin"])), ("normalize_units", true.into()), ("verbose", 2.into())]);
+    let app = matches(&config, &[]);
+    assert_eq!(app.get_one::<u64>("he_poll_interval"), Some(&60));
+    assert_eq!(app.get_many::<String>("info_attributes").unwrap().collect::<Vec<_>>(), ["firmware", "lastCheckin"]);
+    assert!(app.get_flag("normalize_units"));
+    assert_eq!(app.get_one::<u8>("v"), Some(&2));
+
+    // The command line takes precedence over the file
+    let app = matches(&config, &["--hubitat_poll_interval", "5", "-v"]);
+    assert_eq!(app.get_one::<u64>("he_poll_interval"), Some(&5));
+    assert_eq!(app.get_one::<u8>("v"), Some(&1));
+  }
+
+  #[test]
+  fn apply_rejects_unknown_and_invalid_settings() {
+    let e = config(&[("poll_interval", 60.into())]).apply(crate::cli()).unwrap_err().to_string();
+    assert!(e.contains("unknown setting \"poll_interval\""), "{e}");
+    let e = config(&[("config", "other.toml".into())]).apply(crate::cli()).unwrap_err().to_string();
+    assert!(e.contains("unknown setting"), "{e}");
+    let e = config(&[("listener", serde_json::json!({ "ip": "0.0.0.0" }))]).apply(crate::cli()).unwrap_err().to_string();
+    assert!(e.contains("invalid setting value"), "{e}");
+  }
+
+  #[test]
+  fn effective_prints_the_mappings() {
+    let config = config(&[]);
+    let cmd = config.apply(crate::cli()).unwrap();
+    let app = cmd.clone().try_get_matches_from(["hubitat_exporter", "--config", "c.toml"]).unwrap();
+    let mappings = crate::mapping::Mappings { units: vec![crate::mapping::UnitHint { attribute: "temperature".to_string(), driver: None, unit: "°F".to_string() }],
+                                              ..Default::default() };
+    let out = config.effective(&cmd, &app, &[], &mappings).unwrap();
+    assert!(out.contains("[[unit]]\nattribute = \"temperature\"\nunit = \"°F\"\n"), "{out}");
+    assert!(out.contains("hubitat_poll_interval = 30\n"), "{out}");
+  }
+
+  #[test]
+  fn command_line_token_overrides_file_token_file() {
+    let app = matches(&config(&[("hubitat_api_access_token_file", "/run/secrets/token".into())]), &["-t", "clitok"]);
+    assert_eq!(credential(&app, "he_api_token").as_deref(), Some("clitok"));
+    assert_eq!(credential(&app, "he_api_token_file"), None);
+  }
+
+  #[test]
+  fn command_line_token_file_overrides_file_token() {
+    let app = matches(&config(&[("hubitat_api_access_token", "filetok".into())]), &["--hubitat_api_access_token_file", "/run/secrets/token"]);
+    assert_eq!(credential(&app, "he_api_token"), None);
+    assert_eq!(credential(&app, "he_api_token_file").as_deref(), Some("/run/secrets/token"));
+  }
+
+  #[test]
+  fn command_line_password_overrides_file_password_file() {
+    let app = matches(&config(&[("hubitat_auth_pwd_file", "/run/secrets/pwd".into())]), &["-u", "admin", "-p", "clipwd"]);
+    assert_eq!(credential(&app, "he_auth_pwd").as_deref(), Some("clipwd"));
+    assert_eq!(credential(&app, "he_auth_pwd_file"), None);
+  }
+
+  #[test]
+  fn file_credentials_are_kept() {
+    let app = matches(&config(&[("hubitat_api_access_token_file", "/run/secrets/token".into())]), &[]);
+    assert_eq!(credential(&app, "he_api_token"), None);
+    assert_eq!(credential(&app, "he_api_token_file").as_deref(), Some("/run/secrets/token"));
+  }
+
+  #[test]
+  fn both_credential_settings_in_file() {
+    let err = config(&[("hubitat_api_access_token", "filetok".into()), ("hubitat_api_access_token_file", "/run/secrets/token".into())]).apply(crate::cli()).unwrap_err();
+    assert!(err.to_string().contains("hubitat_api_access_token_file"), "{err}");
+  }
+
+  #[test]
+  fn effective_skips_overridden_credential() {
+    let config = config(&[("hubitat_api_access_token_file", "/run/secrets/token".into())]);
+    let cmd = config.apply(crate::cli()).unwrap();
+    let app = cmd.clone().try_get_matches_from(["hubitat_exporter", "--config", "c.toml", "-t", "clitok"]).unwrap();
+    let out = config.effective(&cmd, &app, &[], &Default::default()).unwrap();
+    assert!(out.contains("hubitat_api_access_token = \"<redacted>\""), "{out}");
+    assert!(!out.contains("hubitat_api_access_token_file"), "{out}");
+  }
+}
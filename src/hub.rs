@@ -1,16 +1,90 @@
+use std::collections::HashMap;
+
 use reqwest::blocking::Client;
 
-use serde::{de, Deserialize, Deserializer};
+use crate::{mapping::Mappings, metrics::MetricType};
+
+use serde::{
+  de::{self, IgnoredAny},
+  Deserialize, Deserializer,
+};
 use serde_json::Value;
 
 #[derive(Debug, Default)]
 pub struct HubInfo<'a> {
   pub ip:               Option<&'a str>,
   pub auth_usr:         Option<&'a str>,
-  pub auth_pwd:         Option<&'a str>,
+  pub auth_pwd:         Option<String>,
   pub api_id:           Option<&'a str>,
-  pub api_access_token: Option<&'a str>,
+  pub api_access_token: Option<String>,
   pub client:           Option<Client>,
+  pub api_client:       Option<Client>,
+}
+
+/// How the body returned by a hub endpoint is exposed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HubValueKind {
+  /// Numeric gauge
+  Number,
+  /// Series with the body as the `state` label
+  State,
+  /// Info series with the body as the `value` label
+  Info,
+}
+
+/// Hub endpoint polled for a hub level metric
+#[derive(Debug, Clone)]
+pub struct HubEndpoint {
+  pub metric: String,
+  pub path:   String,
+  pub kind:   HubValueKind,
+}
+
+/// Built-in hub endpoints as `(metric, path, kind)`
+#[rustfmt::skip]
+pub const HUB_ENDPOINTS: &[(&str, &str, HubValueKind)] = &[
+  ("cpu_load_1min",    "/hub/advanced/cpu1min",             HubValueKind::Number),
+  ("cpu_load_5min",    "/hub/advanced/cpu5min",             HubValueKind::Number),
+  ("free_os_memory",   "/hub/advanced/freeOSMemory",        HubValueKind::Number),
+  ("free_jvm_memory",  "/hub/advanced/freeJVMMemory",       HubValueKind::Number),
+  ("total_jvm_memory", "/hub/advanced/totalJVMMemory",      HubValueKind::Number),
+  ("database_size",    "/hub/advanced/databaseSize",        HubValueKind::Number),
+  ("temperature",      "/hub/advanced/internalTempCelsius", HubValueKind::Number),
+  ("uptime_seconds",   "/hub/advanced/uptime",              HubValueKind::Number),
+  ("zwave_status",     "/hub/advanced/zwaveStatus",         HubValueKind::State),
+  ("zigbee_status",    "/hub/advanced/zigbeeStatus",        HubValueKind::State),
+  ("state",            "/hub/advanced/hubState",            HubValueKind::State),
+  ("firmware_version", "/hub/advanced/firmwareVersion",     HubValueKind::Info),
+];
+
+impl HubEndpoint {
+  pub fn defaults() -> Vec<HubEndpoint> { HUB_ENDPOINTS.iter().map(|(metric, path, kind)| HubEndpoint { metric: metric.to_string(), path: path.to_string(), kind: *kind }).collect() }
+
+  /// Parses a `metric=path[:number|state|info]` endpoint definition, the kind defaults to number
+  pub fn parse(s: &str) -> Result<HubEndpoint, String> {
+    let (metric, path) = s.split_once('=').ok_or_else(|| format!("invalid hub endpoint {s:?}, expected metric=path[:kind]"))?;
+    let (path, kind) = match path.rsplit_once(':') {
+      Some((p, "number")) => (p, HubValueKind::Number),
+      Some((p, "state")) => (p, HubValueKind::State),
+      Some((p, "info")) => (p, HubValueKind::Info),
+      _ => (path, HubValueKind::Number),
+    };
+    if metric.trim().is_empty() || !path.starts_with('/') {
+      return Err(format!("invalid hub endpoint {s:?}, expected metric=path[:kind]"));
+    }
+
+    Ok(HubEndpoint { metric: metric.trim().to_string(),
+                     path: path.to_string(),
+                     kind })
+  }
+}
+
+/// Value returned by a hub endpoint
+#[derive(Debug, Clone)]
+pub struct HubMetric {
+  pub metric: String,
+  pub kind:   HubValueKind,
+  pub value:  String,
 }
 
 fn de_strings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
@@ -23,6 +97,19 @@ fn de_strings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::E
   })
 }
 
+/// Hub identity from the hub details endpoint, independent of the device inventory
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HubDetails {
+  #[serde(alias = "hubName")]
+  pub name:          String,
+  pub location_name: String,
+  #[serde(alias = "hubVersion", alias = "firmwareVersionString")]
+  pub version:       String,
+  #[serde(rename = "hardwareID", alias = "hardwareId")]
+  pub hardware_id:   String,
+}
+
 #[derive(Debug, Deserialize, Default, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DeviceInventory {
@@ -34,19 +121,10 @@ pub struct DeviceInventory {
   pub zigbee_id:          String,
   pub device_type_name:   String,
   #[serde(deserialize_with = "de_strings")]
-  pub location_id:        String,
-  #[serde(deserialize_with = "de_strings")]
   pub id:                 String,
   #[serde(deserialize_with = "de_strings")]
-  pub lan_id:             String,
-  #[serde(deserialize_with = "de_strings")]
-  pub hub_id:             String,
-  #[serde(deserialize_with = "de_strings")]
-  pub display_as_child:   String,
-  #[serde(deserialize_with = "de_strings")]
   pub mesh_enabled:       String,
   pub name:               String,
-  pub r#type:             String,
   #[serde(deserialize_with = "de_strings")]
   pub disabled:           String,
   pub status:             String,
@@ -57,13 +135,29 @@ pub struct DeviceInventory {
   #[serde(deserialize_with = "de_strings")]
   pub device_network_id:  String,
   #[serde(deserialize_with = "de_strings")]
-  pub device_type_id:     String,
-  #[serde(deserialize_with = "de_strings")]
   pub last_activity_time: String,
   #[serde(deserialize_with = "de_strings")]
   pub parent_device_id:   String,
-  #[serde(deserialize_with = "de_strings")]
-  pub display_name:       String,
+}
+
+impl DeviceInventory {
+  pub fn is_disabled(&self) -> bool { self.disabled.eq_ignore_ascii_case("true") }
+
+  /// Last activity as a unix timestamp, the hub reports either epoch milliseconds, an RFC 3339 date or a local date without time zone
+  pub fn last_activity_timestamp(&self) -> Option<f64> {
+    let v = self.last_activity_time.trim();
+    if v.is_empty() {
+      return None;
+    }
+    if let Ok(ms) = v.parse::<i64>() {
+      return Some(ms as f64 / 1000.0);
+    }
+    if let Ok(d) = chrono::DateTime::parse_from_rfc3339(v).or_else(|_| chrono::DateTime::parse_from_str(v, "%Y-%m-%dT%H:%M:%S%.f%z")) {
+      return Some(d.timestamp_millis() as f64 / 1000.0);
+    }
+
+    chrono::NaiveDateTime::parse_from_str(v, "%Y-%m-%d %H:%M:%S%.f").ok().and_then(|d| d.and_local_timezone(chrono::Local).earliest()).map(|d| d.timestamp_millis() as f64 / 1000.0)
+  }
 }
 
 #[allow(dead_code)]
@@ -82,8 +176,124 @@ pub struct Device {
   pub label:        String,
   pub r#type:       String,
   pub attributes:   Vec<DeviceAttribute>,
-  pub capabilities: Vec<serde_json::Value>,
-  pub commands:     Vec<String>,
+  pub capabilities: Vec<Capability>,
+  pub commands:     Vec<DeviceCommand>,
+}
+
+/// Capability entry of a device, the Maker API mixes the capability names with objects listing the attributes they provide
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Capability {
+  Name(String),
+  Attributes { attributes: Vec<CapabilityAttribute> },
+  Other(IgnoredAny),
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CapabilityAttribute {
+  pub name:      String,
+  #[serde(default, deserialize_with = "de_strings")]
+  pub data_type: String,
+}
+
+/// Command entry of a device, reported either as a plain name or as a definition
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum DeviceCommand {
+  Name(String),
+  Definition { command: String },
+  Other(IgnoredAny),
+}
+
+impl Device {
+  pub fn capability_names(&self) -> impl Iterator<Item=&str> {
+    self.capabilities.iter().filter_map(|c| {
+                              match c {
+                                Capability::Name(n) => Some(n.as_str()),
+                                _ => None,
+                              }
+                            })
+  }
+
+  /// Data types of the attributes declared by the capabilities, attributes declared without a data type are left out
+  pub fn declared_data_types(capabilities: &[Capability]) -> HashMap<&str, &str> {
+    capabilities.iter()
+                .filter_map(|c| {
+                  match c {
+                    Capability::Attributes { attributes } => Some(attributes),
+                    _ => None,
+                  }
+                })
+                .flatten()
+                .filter(|a| !a.data_type.is_empty())
+                .map(|a| (a.name.as_str(), a.data_type.as_str()))
+                .collect()
+  }
+
+  pub fn command_names(&self) -> impl Iterator<Item=&str> {
+    self.commands.iter().filter_map(|c| {
+                          match c {
+                            DeviceCommand::Name(n) | DeviceCommand::Definition { command: n } => Some(n.as_str()),
+                            DeviceCommand::Other(_) => None,
+                          }
+                        })
+  }
+}
+
+/// Device as returned by the Maker API bulk `devices/all` endpoint, where the attributes are a plain `name -> value` map without data types
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceAll {
+  #[serde(deserialize_with = "de_strings")]
+  pub id:           String,
+  #[serde(deserialize_with = "de_strings")]
+  pub name:         String,
+  #[serde(default, deserialize_with = "de_strings")]
+  pub label:        String,
+  #[serde(default, deserialize_with = "de_strings")]
+  pub r#type:       String,
+  #[serde(default)]
+  pub attributes:   serde_json::Map<String, Value>,
+  #[serde(default)]
+  pub capabilities: Vec<Capability>,
+  #[serde(default)]
+  pub commands:     Vec<DeviceCommand>,
+}
+
+impl From<DeviceAll> for Device {
+  fn from(d: DeviceAll) -> Self {
+    // The data types declared by the capabilities are used first, the others are guessed from the values
+    let declared = Device::declared_data_types(&d.capabilities);
+    let attributes = d.attributes
+                      .into_iter()
+                      .filter_map(|(name, v)| {
+                        let (current_value, data_type) = match v {
+                          Value::Null => return None,
+                          Value::String(s) if declared.contains_key(name.as_str()) => (s, declared[name.as_str()]),
+                          v if declared.contains_key(name.as_str()) => (v.to_string(), declared[name.as_str()]),
+                          Value::Number(n) => (n.to_string(), "NUMBER"),
+                          // Known numeric attributes stay numbers when their value does not parse, so the parse errors are counted as in the device mode
+                          Value::String(s) if parse_numeric(&s).is_some() || is_numeric_attribute(&name) => (s, "NUMBER"),
+                          Value::String(s) => (s, "STRING"),
+                          Value::Bool(b) => (b.to_string(), "STRING"),
+                          v => (v.to_string(), "JSON_OBJECT"),
+                        };
+                        Some(DeviceAttribute { name,
+                                               current_value,
+                                               data_type: data_type.to_string(),
+                                               values: vec![] })
+                      })
+                      .collect();
+
+    Device { id: d.id,
+             name: d.name,
+             label: d.label,
+             r#type: d.r#type,
+             attributes,
+             capabilities: d.capabilities,
+             commands: d.commands }
+  }
 }
 
 #[derive(Debug, Deserialize)]
@@ -97,441 +307,407 @@ pub struct DeviceAttribute {
   pub values:        Vec<String>,
 }
 
+/// Attributes reporting a monotonically increasing total
+const COUNTER_ATTRIBUTES: &[&str] = &["energy"];
+
+/// Numeric attributes of the Hubitat capabilities besides the ones of `UNIT_CONVERSIONS`
+const NUMERIC_ATTRIBUTES: &[&str] = &["airQualityIndex", "carbonDioxide", "colorTemperature", "current", "hue", "illuminance", "pressure", "saturation", "ultravioletIndex"];
+
+/// Attribute that the Hubitat capabilities define as a number
+fn is_numeric_attribute(name: &str) -> bool { NUMERIC_ATTRIBUTES.iter().chain(COUNTER_ATTRIBUTES).any(|a| a.eq_ignore_ascii_case(name)) || UnitConversion::find(name).is_some() }
+
+/// Numeric mapping of an enum attribute, `other` applies to any value not listed
+pub struct EnumMapping {
+  pub attribute: &'static str,
+  pub values:    &'static [(&'static str, f64)],
+  pub other:     Option<f64>,
+}
+
+/// Enum attributes of the Hubitat capabilities, attribute names and values are matched case-insensitively.
+/// The binary attributes such as `lock` and `thermostatFanMode` keep their legacy 0/1 values, `--enum_mode state` tells their values apart.
+#[rustfmt::skip]
+pub const ENUM_MAPPINGS: &[EnumMapping] = &[
+  EnumMapping { attribute: "acceleration",             values: &[("inactive", 0.0), ("active", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "alarm",                    values: &[("off", 0.0), ("inactive", 0.0)], other: Some(1.0) },
+  EnumMapping { attribute: "beacon",                   values: &[("not present", 0.0), ("present", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "button",                   values: &[("pushed", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "camera",                   values: &[("off", 0.0), ("on", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "carbonMonoxide",           values: &[("clear", 0.0), ("tested", 0.0), ("detected", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "colorMode",                values: &[("CT", 0.0), ("RGB", 1.0), ("EFFECTS", 2.0)], other: None },
+  EnumMapping { attribute: "consumableStatus",         values: &[("good", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "contact",                  values: &[("closed", 0.0), ("open", 1.0)], other: Some(1.0) },
+  EnumMapping { attribute: "door",                     values: &[("closed", 0.0), ("closing", 1.0), ("open", 2.0), ("opening", 3.0), ("unknown", 4.0)], other: None },
+  EnumMapping { attribute: "filterStatus",             values: &[("normal", 0.0), ("replace", 1.0)], other: None },
+  EnumMapping { attribute: "healthStatus",             values: &[("offline", 0.0), ("online", 1.0)], other: None },
+  EnumMapping { attribute: "heatAlarm",                values: &[("cleared", 0.0), ("overheat", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "indicatorStatus",          values: &[("never", 0.0), ("when off", 0.0), ("when on", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "lock",                     values: &[("locked", 0.0), ("unlocked", 1.0), ("unlocked with timeout", 1.0), ("unknown", 1.0)], other: Some(1.0) },
+  EnumMapping { attribute: "motion",                   values: &[("inactive", 0.0), ("active", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "mute",                     values: &[("unmuted", 0.0), ("muted", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "naturalGas",               values: &[("clear", 0.0), ("tested", 0.0), ("detected", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "optimisation",             values: &[("inactive", 0.0), ("active", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "powerSource",              values: &[("battery", 0.0), ("dc", 1.0), ("mains", 2.0), ("unknown", 3.0)], other: None },
+  EnumMapping { attribute: "presence",                 values: &[("not present", 0.0), ("present", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "rain",                     values: &[("inactive", 0.0), ("active", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "rainHeavy",                values: &[("inactive", 0.0), ("active", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "securityKeypad",           values: &[("disarmed", 0.0), ("armed home", 1.0), ("armed away", 2.0), ("unknown", 3.0)], other: None },
+  EnumMapping { attribute: "sessionStatus",            values: &[("stopped", 0.0), ("canceled", 0.0), ("paused", 0.0), ("running", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "shock",                    values: &[("clear", 0.0), ("detected", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "sleeping",                 values: &[("not sleeping", 0.0), ("sleeping", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "smoke",                    values: &[("clear", 0.0), ("tested", 0.0), ("detected", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "sound",                    values: &[("not detected", 0.0), ("detected", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "speed",                    values: &[("off", 0.0), ("low", 1.0), ("medium-low", 2.0), ("medium", 3.0), ("medium-high", 4.0), ("high", 5.0), ("on", 6.0), ("auto", 7.0)], other: None },
+  EnumMapping { attribute: "status",                   values: &[("stopped", 0.0), ("playing", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "switch",                   values: &[("off", 0.0), ("on", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "tamper",                   values: &[("clear", 0.0), ("detected", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "thermostatFanMode",        values: &[("off", 0.0), ("on", 1.0), ("auto", 1.0), ("circulate", 1.0)], other: Some(1.0) },
+  EnumMapping { attribute: "thermostatMode",           values: &[("off", 0.0), ("auto", 1.0), ("heat", 2.0), ("cool", 3.0), ("emergency heat", 4.0)], other: None },
+  EnumMapping { attribute: "thermostatOperatingState", values: &[("idle", 0.0), ("heating", 1.0), ("cooling", 2.0), ("pending heat", 3.0), ("pending cool", 4.0), ("vent economizer", 5.0), ("fan only", 6.0)], other: None },
+  EnumMapping { attribute: "thermostatSetpointMode",   values: &[("followSchedule", 0.0)], other: Some(1.0) },
+  EnumMapping { attribute: "timedSession",             values: &[("stopped", 0.0), ("running", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "touch",                    values: &[("touched", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "transportStatus",          values: &[("stopped", 0.0), ("playing", 1.0), ("paused", 2.0)], other: None },
+  EnumMapping { attribute: "valve",                    values: &[("closed", 0.0), ("open", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "water",                    values: &[("dry", 0.0), ("wet", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "windowFunction",           values: &[("inactive", 0.0), ("active", 1.0)], other: Some(0.0) },
+  EnumMapping { attribute: "windowShade",              values: &[("closed", 0.0), ("closing", 0.0), ("opening", 1.0), ("partially open", 1.0), ("open", 1.0)], other: Some(0.0) },
+];
+
+impl EnumMapping {
+  pub fn find(attribute: &str) -> Option<&'static EnumMapping> { ENUM_MAPPINGS.iter().find(|m| m.attribute.eq_ignore_ascii_case(attribute)) }
+
+  pub fn value(&self, value: &str) -> Option<f64> { self.values.iter().find(|(v, _)| v.eq_ignore_ascii_case(value)).map(|(_, n)| *n) }
+}
+
+/// Conversion of the attributes reported in different units to a single base unit, a unit converts with `value * scale + offset`
+pub struct UnitConversion {
+  pub attributes: &'static [&'static str],
+  /// Base unit, also used as the metric name suffix
+  pub unit:       &'static str,
+  /// Unit assumed when the value has no unit and there is no unit hint, none when the unit depends on the hub settings such as the temperature scale
+  pub default:    Option<&'static str>,
+  pub from:       &'static [(&'static str, f64, f64)],
+}
+
+#[rustfmt::skip]
+pub const UNIT_CONVERSIONS: &[UnitConversion] = &[
+  UnitConversion { attributes: &["temperature", "heatingSetpoint", "coolingSetpoint", "thermostatSetpoint"], unit: "celsius", default: None,        from: &[("°C", 1.0, 0.0), ("C", 1.0, 0.0), ("°F", 5.0 / 9.0, -160.0 / 9.0), ("F", 5.0 / 9.0, -160.0 / 9.0)] },
+  UnitConversion { attributes: &["power"],                                                            unit: "watts",   default: Some("W"),   from: &[("W", 1.0, 0.0), ("kW", 1000.0, 0.0), ("mW", 0.001, 0.0)] },
+  UnitConversion { attributes: &["energy"],                                                           unit: "joules",  default: Some("kWh"), from: &[("kWh", 3_600_000.0, 0.0), ("Wh", 3600.0, 0.0), ("J", 1.0, 0.0)] },
+  UnitConversion { attributes: &["voltage"],                                                          unit: "volts",   default: Some("V"),   from: &[("V", 1.0, 0.0), ("mV", 0.001, 0.0)] },
+  UnitConversion { attributes: &["battery", "humidity", "level", "position"],                         unit: "percent", default: Some("%"),   from: &[("%", 1.0, 0.0)] },
+];
+
+impl UnitConversion {
+  pub fn find(attribute: &str) -> Option<&'static UnitConversion> { UNIT_CONVERSIONS.iter().find(|c| c.attributes.iter().any(|a| a.eq_ignore_ascii_case(attribute))) }
+
+  pub fn convert(&self, value: f64, unit: &str) -> Option<f64> { self.from.iter().find(|(u, ..)| *u == unit).or_else(|| self.from.iter().find(|(u, ..)| unit_matches(u, unit))).map(|(_, scale, offset)| value * scale + offset) }
+}
+
+/// Matches a unit ignoring the case of its base symbol only, the SI prefix is case-sensitive so `kwh` is `kWh` but `MW` is not `mW`
+fn unit_matches(known: &str, unit: &str) -> bool {
+  let (known_prefix, known_base) = split_prefix(known);
+  let (prefix, base) = split_prefix(unit);
+  known_prefix == prefix && known_base.eq_ignore_ascii_case(base)
+}
+
+/// Splits the SI prefix from a unit symbol, a lone letter is a base symbol
+fn split_prefix(unit: &str) -> (&str, &str) {
+  match unit.char_indices().nth(1) {
+    Some((i, _)) if unit.starts_with(['G', 'M', 'k', 'm']) => unit.split_at(i),
+    _ => ("", unit),
+  }
+}
+
 impl DeviceAttribute {
-  pub fn get_numeric_value(&self) -> Option<String> {
-    match self {
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "acceleration" =>
-      {
-        if current_value.to_ascii_lowercase() == "active" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "alarm" =>
-      {
-        if current_value.to_ascii_lowercase() == "off" || current_value.to_ascii_lowercase() == "inactive" {
-          Some("0".to_string())
-        } else {
-          Some("1".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "presence" =>
-      {
-        if current_value.to_ascii_lowercase() == "present" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "presence" =>
-      {
-        if current_value.to_ascii_lowercase() == "present" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "switch" =>
-      {
-        if current_value.to_ascii_lowercase() == "on" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "button" =>
-      {
-        if current_value.to_ascii_lowercase() == "pushed" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "carbonMonoxide" =>
-      {
-        if current_value.to_ascii_lowercase() == "detected" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "status" =>
-      {
-        if current_value.to_ascii_lowercase() == "playing" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "consumableStatus" =>
-      {
-        if current_value.to_ascii_lowercase() == "good" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "contact" =>
-      {
-        if current_value.to_ascii_lowercase() == "closed" {
-          Some("0".to_string())
-        } else {
-          Some("1".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "indicatorStatus" =>
-      {
-        if current_value.to_ascii_lowercase() == "when on" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "lock" =>
-      {
-        if current_value.to_ascii_lowercase() == "locked" {
-          Some("0".to_string())
-        } else {
-          Some("1".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "motion" =>
-      {
-        if current_value.to_ascii_lowercase() == "active" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "shock" =>
-      {
-        if current_value.to_ascii_lowercase() == "detected" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "mute" =>
-      {
-        if current_value.to_ascii_lowercase() == "muted" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "sleeping" =>
-      {
-        if current_value.to_ascii_lowercase() == "sleeping" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "smoke" =>
-      {
-        if current_value.to_ascii_lowercase() == "detected" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "sound" =>
-      {
-        if current_value.to_ascii_lowercase() == "detected" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "tamper" =>
-      {
-        if current_value.to_ascii_lowercase() == "detected" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "door" =>
-      {
-        if current_value.to_ascii_lowercase() == "closed" {
-          Some("0".to_string())
-        } else if current_value.to_ascii_lowercase() == "closing" {
-          Some("1".to_string())
-        } else if current_value.to_ascii_lowercase() == "open" {
-          Some("2".to_string())
-        } else if current_value.to_ascii_lowercase() == "opening" {
-          Some("3".to_string())
-        } else if current_value.to_ascii_lowercase() == "unknown" {
-          Some("4".to_string())
-        } else {
-          None
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "thermostatMode" =>
-      {
-        if current_value.to_ascii_lowercase() == "off" {
-          Some("0".to_string())
-        } else if current_value.to_ascii_lowercase() == "auto" {
-          Some("1".to_string())
-        } else if current_value.to_ascii_lowercase() == "heat" {
-          Some("2".to_string())
-        } else if current_value.to_ascii_lowercase() == "cool" {
-          Some("3".to_string())
-        } else if current_value.to_ascii_lowercase() == "emergency heat" {
-          Some("4".to_string())
-        } else {
-          None
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "thermostatFanMode" =>
-      {
-        if current_value.to_ascii_lowercase() == "off" {
-          Some("0".to_string())
-        } else {
-          Some("1".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "thermostatOperatingState" =>
-      {
-        if current_value.to_ascii_lowercase() == "idle" {
-          Some("0".to_string())
-        } else if current_value.to_ascii_lowercase() == "heating" {
-          Some("1".to_string())
-        } else if current_value.to_ascii_lowercase() == "cooling" {
-          Some("2".to_string())
-        } else if current_value.to_ascii_lowercase() == "pending heat" {
-          Some("3".to_string())
-        } else if current_value.to_ascii_lowercase() == "pending cool" {
-          Some("4".to_string())
-        } else if current_value.to_ascii_lowercase() == "vent economizer" {
-          Some("5".to_string())
-        } else if current_value.to_ascii_lowercase() == "fan only" {
-          Some("6".to_string())
-        } else {
-          None
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "thermostatSetpointMode" =>
-      {
-        if current_value.to_ascii_lowercase() == "followSchedule" {
-          Some("0".to_string())
-        } else {
-          Some("1".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "timedSession" =>
-      {
-        if current_value.to_ascii_lowercase() == "running" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "touch" =>
-      {
-        if current_value.to_ascii_lowercase() == "touched" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "valve" =>
-      {
-        if current_value.to_ascii_lowercase() == "open" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "camera" =>
-      {
-        if current_value.to_ascii_lowercase() == "on" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "water" =>
-      {
-        if current_value.to_ascii_lowercase() == "wet" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "windowShade" =>
-      {
-        if current_value.to_ascii_lowercase() == "opening" || current_value.to_ascii_lowercase() == "partially open" || current_value.to_ascii_lowercase() == "open" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "optimisation" =>
-      {
-        if current_value.to_ascii_lowercase() == "active" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "windowFunction" =>
-      {
-        if current_value.to_ascii_lowercase() == "active" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "rain" =>
-      {
-        if current_value.to_ascii_lowercase() == "active" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "rainHeavy" =>
-      {
-        if current_value.to_ascii_lowercase() == "active" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref name,
-             current_value,
-             .. }
-        if name.to_ascii_lowercase() == "heatAlarm" =>
-      {
-        if current_value.to_ascii_lowercase() == "overheat" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
-      },
-      Self { ref data_type,
-             current_value,
-             .. }
-        if data_type == "NUMBER" =>
-      {
-        if !current_value.is_empty() {
-          Some(current_value.to_string())
-        } else {
-          None
-        }
-      },
-      Self { ref current_value, .. } if current_value.to_ascii_lowercase() == "on" => {
-        if current_value.to_ascii_lowercase() == "on" {
-          Some("1".to_string())
-        } else {
-          Some("0".to_string())
-        }
+  pub fn help(&self) -> String { format!("Hubitat device attribute {name} ({data_type})", name = self.name, data_type = self.data_type) }
+
+  pub fn metric_type(&self) -> MetricType {
+    if COUNTER_ATTRIBUTES.iter().any(|c| c.eq_ignore_ascii_case(&self.name)) {
+      MetricType::Counter
+    } else {
+      MetricType::Gauge
+    }
+  }
+
+  /// Numeric value of the attribute, the user `mappings` are looked up before the built-in `ENUM_MAPPINGS`
+  pub fn get_numeric_value(&self, driver: &str, mappings: &Mappings) -> Option<f64> {
+    let user = mappings.find(&self.name, driver);
+    let builtin = EnumMapping::find(&self.name);
+    let mapped = user.and_then(|m| m.value(&self.current_value)).or_else(|| builtin.and_then(|m| m.value(&self.current_value))).or_else(|| user.and_then(|m| m.other)).or_else(|| builtin.and_then(|m| m.other));
+    if mapped.is_some() {
+      return mapped;
+    }
+
+    if self.data_type == "NUMBER" {
+      parse_numeric(&self.current_value)
+    } else if self.current_value.eq_ignore_ascii_case("on") {
+      Some(1.0)
+    } else {
+      None
+    }
+  }
+
+  /// Value of a `NUMBER` attribute converted to its base unit, the unit comes from the value itself, the user unit hints or the Hubitat default.
+  /// `None` when the unit is unknown, for instance a temperature without unit nor hint which can be in °C or °F.
+  pub fn get_normalized_value(&self, driver: &str, mappings: &Mappings) -> Option<(f64, &'static str)> {
+    if self.data_type != "NUMBER" {
+      return None;
+    }
+
+    let conv = UnitConversion::find(&self.name)?;
+    let (n, unit) = parse_numeric_unit(&self.current_value)?;
+    let unit = match unit {
+      "" => {
+        let Some(unit) = mappings.unit_hint(&self.name, driver).or(conv.default) else {
+          debug!("attribute {:?} has no unit nor unit hint, not normalizing", self.name);
+          return None;
+        };
+        unit
+      },
+      unit => unit,
+    };
+    match conv.convert(n, unit) {
+      Some(n) => Some((n, conv.unit)),
+      None => {
+        debug!("attribute {:?} unit {:?} is unknown, not normalizing", self.name, unit);
+        None
       },
-      _ => None,
     }
   }
+
+  /// A `NUMBER` attribute with a value that could not be parsed, usually a broken driver
+  pub fn is_unparseable(&self, driver: &str, mappings: &Mappings) -> bool { self.data_type == "NUMBER" && !self.current_value.trim().is_empty() && self.get_numeric_value(driver, mappings).is_none() }
+}
+
+/// Parses a driver reported number, accepting a trailing unit (`21.5 °C`, `95%`), thousands and decimal separators (`1,234.5`, `21,5`) and booleans.
+/// Non finite values are rejected as they can not be aggregated.
+pub fn parse_numeric(v: &str) -> Option<f64> { parse_numeric_unit(v).map(|(n, _)| n) }
+
+/// Same as `parse_numeric` but also returns the trailing unit, empty when there is none
+pub fn parse_numeric_unit(v: &str) -> Option<(f64, &str)> {
+  let v = v.trim();
+  if v.eq_ignore_ascii_case("true") {
+    return Some((1.0, ""));
+  } else if v.eq_ignore_ascii_case("false") {
+    return Some((0.0, ""));
+  }
+
+  if let Ok(n) = v.parse::<f64>() {
+    return Some((n, "")).filter(|(n, _)| n.is_finite());
+  }
+
+  // Split the number from its unit, the unit can not contain digits
+  let end = v.find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | ',' | '-' | '+'))).unwrap_or(v.len());
+  let (num, unit) = v.split_at(end);
+  let unit = unit.trim();
+  if num.is_empty() || unit.chars().count() > 8 || unit.chars().any(|c| c.is_ascii_digit()) {
+    return None;
+  }
+
+  let num = match (num.rfind(','), num.rfind('.')) {
+    // Both separators, the last one is the decimal separator
+    (Some(c), Some(d)) if c > d => num.replace('.', "").replace(',', "."),
+    (Some(_), Some(_)) => num.replace(',', ""),
+    // Only commas, groups of three digits are thousands otherwise a single comma is the decimal separator
+    (Some(_), None) => {
+      let groups: Vec<&str> = num.split(',').collect();
+      let head = groups[0].trim_start_matches(['-', '+']);
+      if !head.is_empty() && head.len() <= 3 && groups[1..].iter().all(|g| g.len() == 3) {
+        num.replace(',', "")
+      } else if groups.len() == 2 {
+        num.replace(',', ".")
+      } else {
+        return None;
+      }
+    },
+    _ => num.to_string(),
+  };
+
+  num.parse::<f64>().ok().filter(|n| n.is_finite()).map(|n| (n, unit))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn attribute(name: &str, value: &str) -> DeviceAttribute { DeviceAttribute { name: name.to_string(), current_value: value.to_string(), data_type: "ENUM".to_string(), values: vec![] } }
+
+  fn enum_value(name: &str, value: &str) -> Option<f64> { attribute(name, value).get_numeric_value("Generic Driver", &Mappings::default()) }
+
+  #[test]
+  fn enum_mappings_per_capability() {
+    #[rustfmt::skip]
+    let cases = [
+      ("acceleration", "active", 1.0),
+      ("alarm", "siren", 1.0),
+      ("beacon", "present", 1.0),
+      ("button", "pushed", 1.0),
+      ("camera", "on", 1.0),
+      ("carbonMonoxide", "detected", 1.0),
+      ("colorMode", "EFFECTS", 2.0),
+      ("consumableStatus", "good", 1.0),
+      ("contact", "open", 1.0),
+      ("door", "opening", 3.0),
+      ("filterStatus", "replace", 1.0),
+      ("healthStatus", "online", 1.0),
+      ("heatAlarm", "overheat", 1.0),
+      ("indicatorStatus", "when on", 1.0),
+      ("lock", "unlocked with timeout", 1.0),
+      ("motion", "active", 1.0),
+      ("mute", "muted", 1.0),
+      ("naturalGas", "detected", 1.0),
+      ("optimisation", "active", 1.0),
+      ("powerSource", "mains", 2.0),
+      ("presence", "not present", 0.0),
+      ("rain", "active", 1.0),
+      ("rainHeavy", "active", 1.0),
+      ("securityKeypad", "armed away", 2.0),
+      ("sessionStatus", "running", 1.0),
+      ("shock", "detected", 1.0),
+      ("sleeping", "sleeping", 1.0),
+      ("smoke", "tested", 0.0),
+      ("sound", "detected", 1.0),
+      ("speed", "medium-high", 4.0),
+      ("status", "playing", 1.0),
+      ("switch", "on", 1.0),
+      ("tamper", "detected", 1.0),
+      ("thermostatFanMode", "auto", 1.0),
+      ("thermostatMode", "emergency heat", 4.0),
+      ("thermostatOperatingState", "fan only", 6.0),
+      ("thermostatSetpointMode", "followSchedule", 0.0),
+      ("timedSession", "running", 1.0),
+      ("touch", "touched", 1.0),
+      ("transportStatus", "paused", 2.0),
+      ("valve", "open", 1.0),
+      ("water", "wet", 1.0),
+      ("windowFunction", "active", 1.0),
+      ("windowShade", "partially open", 1.0),
+    ];
+    assert_eq!(cases.len(), ENUM_MAPPINGS.len());
+
+    for (name, value, expected) in cases {
+      assert_eq!(enum_value(name, value), Some(expected), "{name}={value}");
+    }
+  }
+
+  #[test]
+  fn enum_mappings_camel_case_attributes() {
+    assert_eq!(enum_value("carbonMonoxide", "clear"), Some(0.0));
+    assert_eq!(enum_value("thermostatMode", "cool"), Some(3.0));
+    assert_eq!(enum_value("windowShade", "closing"), Some(0.0));
+    assert_eq!(enum_value("heatAlarm", "cleared"), Some(0.0));
+    assert_eq!(enum_value("thermostatSetpointMode", "holdIndefinite"), Some(1.0));
+  }
+
+  #[test]
+  fn enum_mappings_ignore_case() {
+    assert_eq!(enum_value("CARBONMONOXIDE", "Detected"), Some(1.0));
+    assert_eq!(enum_value("thermostatmode", "HEAT"), Some(2.0));
+    assert_eq!(enum_value("WindowShade", "Partially Open"), Some(1.0));
+    assert!(EnumMapping::find("THERMOSTATSETPOINTMODE").is_some());
+  }
+
+  #[test]
+  fn enum_mappings_other_fallback() {
+    assert_eq!(enum_value("contact", "ajar"), Some(1.0));
+    assert_eq!(enum_value("switch", "standby"), Some(0.0));
+    assert_eq!(enum_value("alarm", "strobe"), Some(1.0));
+  }
+
+  #[test]
+  fn enum_mappings_unknown_values() {
+    assert_eq!(enum_value("door", "stuck"), None);
+    assert_eq!(enum_value("thermostatMode", "dry"), None);
+    assert_eq!(EnumMapping::find("door").unwrap().value("stuck"), None);
+  }
+
+  #[test]
+  fn bulk_parse_errors_of_numeric_attributes() {
+    let d: DeviceAll = serde_json::from_value(serde_json::json!({"id": "1", "name": "Plug", "attributes": {"power": "NaN", "temperature": "21,5 °C", "switch": "on", "firmware": "v1"}})).unwrap();
+    let d = Device::from(d);
+    let get = |name: &str| d.attributes.iter().find(|a| a.name == name).unwrap();
+
+    assert!(get("power").is_unparseable("Plug", &Mappings::default()));
+    assert!(!get("temperature").is_unparseable("Plug", &Mappings::default()));
+    assert_eq!(get("switch").data_type, "STRING");
+    assert!(!get("firmware").is_unparseable("Plug", &Mappings::default()));
+  }
+
+  #[test]
+  fn bulk_declared_data_types() {
+    let d: DeviceAll = serde_json::from_value(serde_json::json!({"id": "1", "name": "Plug", "attributes": {"firmware": "1.2", "power": "3"}, "capabilities": ["Switch", {"attributes": [{"name": "firmware", "dataType": "STRING"}, {"name": "power", "dataType": null}]}, 42]})).unwrap();
+    let d = Device::from(d);
+    let get = |name: &str| d.attributes.iter().find(|a| a.name == name).unwrap();
+
+    assert_eq!(get("firmware").data_type, "STRING");
+    assert_eq!(get("power").data_type, "NUMBER");
+    assert_eq!(d.capability_names().collect::<Vec<_>>(), ["Switch"]);
+  }
+
+  #[test]
+  fn normalized_temperature_needs_a_unit() {
+    let temp = |v: &str| {
+      DeviceAttribute { data_type: "NUMBER".to_string(),
+                        ..attribute("temperature", v) }
+    };
+    let hinted = Mappings { units: vec![crate::mapping::UnitHint { attribute: "temperature".to_string(), driver: None, unit: "°F".to_string() }],
+                            ..Default::default() };
+    assert_eq!(temp("72").get_normalized_value("Thermostat", &Mappings::default()), None);
+    assert_eq!(temp("72").get_numeric_value("Thermostat", &Mappings::default()), Some(72.0));
+    assert_eq!(temp("72 °F").get_normalized_value("Thermostat", &Mappings::default()), Some((200.0 / 9.0, "celsius")));
+    assert_eq!(temp("72").get_normalized_value("Thermostat", &hinted), Some((200.0 / 9.0, "celsius")));
+
+    let power = DeviceAttribute { data_type: "NUMBER".to_string(),
+                                  ..attribute("power", "12.5") };
+    assert_eq!(power.get_normalized_value("Plug", &Mappings::default()), Some((12.5, "watts")));
+  }
+
+  #[test]
+  fn unit_prefix_is_case_sensitive() {
+    let power = UnitConversion::find("power").unwrap();
+    assert_eq!(power.convert(2.0, "kW"), Some(2000.0));
+    assert_eq!(power.convert(2.0, "w"), Some(2.0));
+    assert_eq!(power.convert(2.0, "mw"), Some(0.002));
+    assert_eq!(power.convert(2.0, "MW"), None);
+    assert_eq!(power.convert(2.0, "KW"), None);
+
+    let energy = UnitConversion::find("energy").unwrap();
+    assert_eq!(energy.convert(1.0, "kwh"), Some(3_600_000.0));
+    assert_eq!(energy.convert(1.0, "WH"), Some(3600.0));
+  }
+
+  #[test]
+  fn parse_numeric_separators() {
+    assert_eq!(parse_numeric("1,234"), Some(1234.0));
+    assert_eq!(parse_numeric("-1,234"), Some(-1234.0));
+    assert_eq!(parse_numeric("1,234,567"), Some(1_234_567.0));
+    assert_eq!(parse_numeric("21,5"), Some(21.5));
+    assert_eq!(parse_numeric("1,2345"), Some(1.2345));
+    assert_eq!(parse_numeric("1,234.5"), Some(1234.5));
+    assert_eq!(parse_numeric("1.234,5"), Some(1234.5));
+    assert_eq!(parse_numeric("1,23,4"), None);
+  }
+
+  #[test]
+  fn parse_numeric_units() {
+    assert_eq!(parse_numeric_unit("21.5 °C"), Some((21.5, "°C")));
+    assert_eq!(parse_numeric_unit("95%"), Some((95.0, "%")));
+    assert_eq!(parse_numeric_unit(" 12 "), Some((12.0, "")));
+    assert_eq!(parse_numeric_unit("21,5 kWh"), Some((21.5, "kWh")));
+    assert_eq!(parse_numeric_unit("5 m3"), None);
+    assert_eq!(parse_numeric_unit("5 verylongunit"), None);
+    assert_eq!(parse_numeric_unit("°C"), None);
+  }
+
+  #[test]
+  fn parse_numeric_special_values() {
+    assert_eq!(parse_numeric("true"), Some(1.0));
+    assert_eq!(parse_numeric("FALSE"), Some(0.0));
+    assert_eq!(parse_numeric("NaN"), None);
+    assert_eq!(parse_numeric("inf"), None);
+    assert_eq!(parse_numeric("-infinity"), None);
+    assert_eq!(parse_numeric(""), None);
+    assert_eq!(parse_numeric("on"), None);
+  }
 }
@@ -1,34 +1,78 @@
+mod config;
 mod hub;
-
-use std::{collections::HashMap, io::Write};
+mod mapping;
+mod metrics;
+mod secret;
+
+use std::{
+  collections::HashMap,
+  io::Write,
+  sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex, RwLock,
+  },
+  thread,
+  time::{Duration, Instant},
+};
 
 #[macro_use]
 extern crate log;
 extern crate env_logger;
 
 extern crate clap;
-use clap::{Arg, ArgMatches, Command};
+use clap::{Arg, Command};
 
 extern crate tiny_http;
-use tiny_http::{Response, Server};
+use tiny_http::{Header, Response, Server};
 
 use anyhow::{anyhow, Result};
 use convert_case::{Case, Casing};
 use reqwest::blocking::RequestBuilder;
 
+fn cli() -> Command {
+  Command::new("").version(env!("CARGO_PKG_VERSION"))
+                  .author(env!("CARGO_PKG_AUTHORS"))
+                  .about(env!("CARGO_PKG_DESCRIPTION"))
+                  .arg(Arg::new("listener").long("listener").env("LISTENER").default_value("0.0.0.0:8000").num_args(1))
+                  .arg(Arg::new("he_ip").short('i').long("hubitat_ip").env("HE_IP").help("Hubitat Hub IP").required_unless_present("config").num_args(1))
+                  .arg(Arg::new("he_app_id").short('a').long("hubitat_app_id").env("HE_APP_ID").help("Hubitat APP ID").required_unless_present("config").num_args(1))
+                  .arg(Arg::new("he_api_token").short('t').long("hubitat_api_access_token").env("HE_API_TOKEN").help("Hubitat API TOKEN").required_unless_present_any(["config", "he_api_token_file"]).num_args(1))
+                  .arg(Arg::new("he_api_token_file").long("hubitat_api_access_token_file").env("HE_API_TOKEN_FILE").help("File holding the Hubitat API TOKEN, re-read on every poll").conflicts_with("he_api_token").num_args(1))
+                  .arg(Arg::new("he_dd").short('d').long("hubitat_device_details").env("HE_DD").help("Add extra detailed labels").action(clap::ArgAction::SetTrue).default_missing_value("false").required(false))
+                  .arg(Arg::new("he_fetch_mode").short('m').long("hubitat_fetch_mode").env("HE_FETCH_MODE").help("Device fetch mode, bulk is faster but guesses the attribute data types and falls back to device when the devices/all endpoint is unavailable").value_parser(["bulk", "device"]).default_value("device").num_args(1))
+                  .arg(Arg::new("he_workers").short('w').long("hubitat_workers").env("HE_WORKERS").help("Number of concurrent device detail requests").value_parser(clap::value_parser!(usize)).default_value("8").num_args(1))
+                  .arg(Arg::new("he_poll_interval").short('n').long("hubitat_poll_interval").env("HE_POLL_INTERVAL").help("Seconds between background hub polls").value_parser(clap::value_parser!(u64).range(1..)).default_value("30").num_args(1))
+                  .arg(Arg::new("metric_namespace").long("metric_namespace").env("METRIC_NAMESPACE").help("Prefix of the device and hub metric names").value_parser(|ns: &str| if metrics::is_valid_namespace(ns) { Ok(ns.to_string()) } else { Err(format!("invalid metric namespace {ns:?}")) }).default_value("hubitat_").num_args(1))
+                  .arg(Arg::new("legacy_metric_names").long("legacy_metric_names").env("LEGACY_METRIC_NAMES").help("Keep the legacy unprefixed device and hub_ metric names").action(clap::ArgAction::SetTrue).conflicts_with("metric_namespace"))
+                  .arg(Arg::new("attribute_mode").long("attribute_mode").env("ATTRIBUTE_MODE").help("Expose each device attribute as its own metric or as the attribute label of a single metric").value_parser(["metric", "label"]).default_value("metric").num_args(1))
+                  .arg(Arg::new("enum_mode").long("enum_mode").env("ENUM_MODE").help("Expose the enum attributes as a mapped number or as one series per state").value_parser(["number", "state"]).default_value("number").num_args(1))
+                  .arg(Arg::new("info_attributes").long("info_attributes").env("INFO_ATTRIBUTES").help("Comma separated non numeric attributes to expose as info metrics, * for all").value_delimiter(',').action(clap::ArgAction::Append))
+                  .arg(Arg::new("info_max_length").long("info_max_length").env("INFO_MAX_LENGTH").help("Longest attribute value exposed as an info metric").value_parser(clap::value_parser!(usize)).default_value("64").num_args(1))
+                  .arg(Arg::new("normalize_units").long("normalize_units").env("NORMALIZE_UNITS").help("Convert temperature, power, energy, voltage and percent attributes to their base unit").action(clap::ArgAction::SetTrue))
+                  .arg(Arg::new("stale_threshold").long("stale_threshold").env("STALE_THRESHOLD").help("Seconds without activity after which a device is stale, 0 disables it").value_parser(clap::value_parser!(u64)).default_value("86400").num_args(1))
+                  .arg(Arg::new("suppress_stale").long("suppress_stale").env("SUPPRESS_STALE").help("Skip the attribute series of the stale devices").action(clap::ArgAction::SetTrue))
+                  .arg(Arg::new("mapping_file").long("mapping_file").env("MAPPING_FILE").help("TOML file with custom attribute value mappings, reloaded when modified").num_args(1))
+                  .arg(Arg::new("he_hub_endpoint").long("hubitat_hub_endpoint").env("HE_HUB_ENDPOINT").help("Extra hub endpoint to poll as metric=path[:number|state|info], can be repeated").value_parser(hub::HubEndpoint::parse).value_delimiter(';').action(clap::ArgAction::Append))
+                  .arg(Arg::new("config").long("config").env("CONFIG_FILE").help("TOML or YAML config file with the settings, hubs, probe modules, device filters and mappings, overridden by the flags and the environment").num_args(1))
+                  .arg(Arg::new("check_config").long("check-config").help("Validate and print the effective config with the credentials redacted").action(clap::ArgAction::SetTrue))
+                  .arg(Arg::new("he_auth_usr").short('u').long("hubitat_auth_usr").env("HE_AUTH_USR").help("Hubitat Hub Username").required(false).num_args(1))
+                  .arg(Arg::new("he_auth_pwd").short('p').long("hubitat_auth_pwd").env("HE_AUTH_PWD").help("Hubitat Hub Password").requires("he_auth_usr").required(false).num_args(1))
+                  .arg(Arg::new("he_auth_pwd_file").long("hubitat_auth_pwd_file").env("HE_AUTH_PWD_FILE").help("File holding the Hubitat Hub Password, re-read on every poll").requires("he_auth_usr").conflicts_with("he_auth_pwd").num_args(1))
+                  .arg(Arg::new("v").short('v').long("verbose").env("VERBOSE").action(clap::ArgAction::Count).required(false).help("Log verbosity (-v, -vv, -vvv...)"))
+}
+
 fn main() {
-  let app = Command::new("").version(env!("CARGO_PKG_VERSION"))
-                            .author(env!("CARGO_PKG_AUTHORS"))
-                            .about(env!("CARGO_PKG_DESCRIPTION"))
-                            .arg(Arg::new("listener").long("listener").env("LISTENER").default_value("0.0.0.0:8000").num_args(1))
-                            .arg(Arg::new("he_ip").short('i').long("hubitat_ip").env("HE_IP").help("Hubitat Hub IP").required(true).num_args(1))
-                            .arg(Arg::new("he_app_id").short('a').long("hubitat_app_id").env("HE_APP_ID").help("Hubitat APP ID").required(true).num_args(1))
-                            .arg(Arg::new("he_api_token").short('t').long("hubitat_api_access_token").env("HE_API_TOKEN").help("Hubitat API TOKEN").required(true).num_args(1))
-                            .arg(Arg::new("he_dd").short('d').long("hubitat_device_details").env("HE_DD").help("Add extra detailed labels").action(clap::ArgAction::SetTrue).default_missing_value("false").required(false))
-                            .arg(Arg::new("he_auth_usr").short('u').long("hubitat_auth_usr").env("HE_AUTH_USR").help("Hubitat Hub Username").required(false).num_args(1))
-                            .arg(Arg::new("he_auth_pwd").short('p').long("hubitat_auth_pwd").env("HE_AUTH_PWD").help("Hubitat Hub Password").requires("he_auth_usr").required(false).num_args(1))
-                            .arg(Arg::new("v").short('v').action(clap::ArgAction::Count).required(false).help("Log verbosity (-v, -vv, -vvv...)"))
-                            .get_matches();
+  // The config file provides the defaults of the flags, so it is loaded before they are parsed
+  let config = match config::path_from_args().map(|f| config::Config::load(&f)) {
+    Some(Ok(c)) => c,
+    Some(Err(e)) => cli().error(clap::error::ErrorKind::InvalidValue, format!("{e:?}")).exit(),
+    None => config::Config::default(),
+  };
+  let cmd = match config.apply(cli()) {
+    Ok(cmd) => cmd,
+    Err(e) => cli().error(clap::error::ErrorKind::UnknownArgument, format!("{e:?}")).exit(),
+  };
+  let app = cmd.clone().get_matches();
 
   match app.get_one::<u8>("v").unwrap() {
     0 => std::env::set_var("RUST_LOG", "error"),
@@ -39,94 +83,618 @@ fn main() {
     _ => std::env::set_var("RUST_LOG", "trace"),
   }
 
-  env_logger::Builder::from_default_env().format(|buf, record| writeln!(buf, "{} {} {}:{} [{}] - {}", chrono::Local::now().format("%Y-%m-%dT%H:%M:%S"), record.module_path().unwrap_or("unknown"), record.file().unwrap_or("unknown"), record.line().unwrap_or(0), record.level(), record.args())).init();
+  env_logger::Builder::from_default_env().format(|buf, record| writeln!(buf, "{} {} {}:{} [{}] - {}", chrono::Local::now().format("%Y-%m-%dT%H:%M:%S"), record.module_path().unwrap_or("unknown"), record.file().unwrap_or("unknown"), record.line().unwrap_or(0), record.level(), secret::redact(&record.args().to_string()))).init();
+
+  // The hub given on the command line is scraped along the hubs of the config file
+  let mut hubs = config.hub.clone();
+  match (app.get_one::<String>("he_ip"), app.get_one::<String>("he_app_id"), app.contains_id("he_api_token") || app.contains_id("he_api_token_file")) {
+    (Some(ip), Some(app_id), true) => {
+      hubs.insert(0, config::HubConfig { name: ip.to_string(),
+                                         ip: ip.to_string(),
+                                         app_id: app_id.to_string(),
+                                         api_token: config::credential(&app, "he_api_token").unwrap_or_default(),
+                                         api_token_file: config::credential(&app, "he_api_token_file"),
+                                         auth_usr: app.get_one::<String>("he_auth_usr").cloned(),
+                                         auth_pwd: config::credential(&app, "he_auth_pwd"),
+                                         auth_pwd_file: config::credential(&app, "he_auth_pwd_file"),
+                                         details: app.get_flag("he_dd"),
+                                         ..Default::default() })
+    },
+    (None, None, false) => {},
+    _ => cmd.clone().error(clap::error::ErrorKind::MissingRequiredArgument, "the hub given on the command line needs --hubitat_ip, --hubitat_app_id and --hubitat_api_access_token or --hubitat_api_access_token_file").exit(),
+  }
+  if let Err(e) = config::check_hub_names(&hubs) {
+    cmd.clone().error(clap::error::ErrorKind::ArgumentConflict, format!("{e}, the hub given on the command line is named after its IP")).exit();
+  }
+  if hubs.is_empty() && config.module.is_empty() {
+    error!("no hub or probe module configured");
+    std::process::exit(1);
+  }
+
+  let workers = *app.get_one::<usize>("he_workers").unwrap();
+  let mut endpoints = hub::HubEndpoint::defaults();
+  for e in app.get_many::<hub::HubEndpoint>("he_hub_endpoint").unwrap_or_default() {
+    endpoints.retain(|d| d.metric != e.metric);
+    endpoints.push(e.clone());
+  }
+  let popts = PollOptions { bulk: app.get_one::<String>("he_fetch_mode").map(|s| s.as_str()) == Some("bulk"),
+                            workers,
+                            interval: Duration::from_secs(*app.get_one::<u64>("he_poll_interval").unwrap()),
+                            endpoints };
+  let opts = metrics::Options { namespace: if app.get_flag("legacy_metric_names") { String::new() } else { app.get_one::<String>("metric_namespace").unwrap().to_string() }, attribute_label: app.get_one::<String>("attribute_mode").map(|s| s.as_str()) == Some("label"), enum_states: app.get_one::<String>("enum_mode").map(|s| s.as_str()) == Some("state"), info_attributes: app.get_many::<String>("info_attributes").unwrap_or_default().cloned().collect(), info_max_length: *app.get_one::<usize>("info_max_length").unwrap(), normalize_units: app.get_flag("normalize_units"), stale_threshold: *app.get_one::<u64>("stale_threshold").unwrap(), suppress_stale: app.get_flag("suppress_stale"), devices: config.devices.clone() };
+
+  // Without a mapping file the mappings are read from the config file
+  let mappings = match app.get_one::<String>("mapping_file").or(app.get_one::<String>("config")) {
+    Some(f) => {
+      match mapping::Mappings::load(f) {
+        Ok(m) => {
+          info!("loaded {} mappings from {:?}", m.mappings.len(), f);
+          RwLock::new(m)
+        },
+        Err(e) => {
+          error!("{:?}", e);
+          std::process::exit(1);
+        },
+      }
+    },
+    None => RwLock::new(mapping::Mappings::default()),
+  };
 
-  let mut he = hub::HubInfo { ip: app.get_one::<String>("he_ip").map(|s| s.as_str()), auth_usr: None, auth_pwd: None, api_id: app.get_one::<String>("he_app_id").map(|s| s.as_str()), api_access_token: app.get_one::<String>("he_api_token").map(|s| s.as_str()), client: None };
+  if app.get_flag("check_config") {
+    match config.effective(&cmd, &app, &hubs, &mappings.read().unwrap()) {
+      Ok(c) => print!("{c}"),
+      Err(e) => {
+        error!("{:?}", e);
+        std::process::exit(1);
+      },
+    }
+    return;
+  }
 
   let listener = app.get_one::<String>("listener").unwrap();
   if let Ok(server) = Server::http(listener) {
     info!("started on http://{}", listener);
 
-    if app.get_flag("he_dd") {
-      info!("detailed mode is turned on");
-      get_log(&mut he, &app);
-    }
+    let snapshots: Vec<RwLock<Option<Snapshot>>> = hubs.iter().map(|_| RwLock::new(None)).collect();
+    // Ready after the first successful poll of a hub, right away when only probing
+    let ready = AtomicBool::new(hubs.is_empty());
 
-    for request in server.incoming_requests() {
-      let hub_metrics = get_hub_metrics(&mut he);
-      trace!("hub_metrics:{:#?}", hub_metrics);
+    thread::scope(|s| {
+      // Every hub is polled by its own thread, so an offline hub does not delay the others
+      for (h, snapshot) in hubs.iter().zip(snapshots.iter()) {
+        let (popts, mappings, ready) = (&popts, &mappings, &ready);
+        s.spawn(move || poll(h, popts, snapshot, mappings, ready));
+      }
 
-      let dev_inv = get_device_inventory(&mut he, &app);
-      trace!("dev_inv:{:#?}", dev_inv);
+      for request in server.incoming_requests() {
+        let format = metrics::Format::negotiate(request.headers().iter().find(|h| h.field.equiv("Accept")).map(|h| h.value.as_str()));
+
+        match request.url().split('?').next().unwrap_or_default() {
+          "/metrics" => {
+            let mut metrics = metrics::Metrics::new(&opts.namespace);
+            for (h, snapshot) in hubs.iter().zip(snapshots.iter()) {
+              metrics.set_const_labels(hub_labels(h));
+              match snapshot.read().unwrap().as_ref() {
+                Some(snap) => build_metrics(&mut metrics, snap, &opts, &mappings.read().unwrap()),
+                None => {
+                  warn!("no snapshot available yet for hub {:?}", h.name);
+                  add_hub_up(&mut metrics, false);
+                },
+              }
+            }
+            respond(request, 200, format.content_type(), metrics.render(format));
+          },
+          "/probe" => {
+            // Probes query the hub synchronously, they get their own thread so they do not block the other scrapes
+            let (config, popts, opts, mappings) = (&config, &popts, &opts, &mappings);
+            s.spawn(move || {
+               match probe(request.url(), config, popts, opts, mappings) {
+                 Ok(m) => respond(request, 200, format.content_type(), m.render(format)),
+                 Err(e) => {
+                   warn!("probe {:?} failed: {:?}", request.url(), e);
+                   respond(request, 400, "text/plain; charset=utf-8", format!("{e}\n"));
+                 },
+               }
+             });
+          },
+          "/" => respond(request, 200, "text/html; charset=utf-8", landing_page(&hubs, &config)),
+          "/-/healthy" => respond(request, 200, "text/plain; charset=utf-8", "Healthy\n".to_string()),
+          "/-/ready" if ready.load(Ordering::Relaxed) => respond(request, 200, "text/plain; charset=utf-8", "Ready\n".to_string()),
+          "/-/ready" => respond(request, 503, "text/plain; charset=utf-8", "Not ready, waiting for the first successful hub poll\n".to_string()),
+          _ => respond(request, 404, "text/plain; charset=utf-8", "Not Found\n".to_string()),
+        }
+      }
+    });
+  } else {
+    error!("Starting web server with listener {:?}", listener);
+  }
+}
+
+fn respond(request: tiny_http::Request, status: u16, content_type: &str, body: String) {
+  let response = Response::from_string(body).with_status_code(status).with_header(Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap());
+  let _ = request.respond(response);
+}
+
+/// HTML page served on `/`, linking to the endpoints with the build information
+fn landing_page(hubs: &[config::HubConfig], config: &config::Config) -> String {
+  let escape = |v: &str| v.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+  let hubs = hubs.iter().map(|h| format!("<li>{} ({})</li>", escape(&h.name), escape(&h.ip))).collect::<String>();
+  let probes = config.module.keys().map(|m| format!("<li><a href=\"/probe?module={m}&amp;target=\">/probe?module={m}&amp;target=</a></li>", m = escape(m))).collect::<String>();
+
+  format!(
+          r#"<!DOCTYPE html>
+<html>
+<head><title>{name}</title></head>
+<body>
+<h1>{name}</h1>
+<p>{description}</p>
+<p>Version {version}, <a href="{repository}">{repository}</a></p>
+<h2>Endpoints</h2>
+<ul>
+<li><a href="/metrics">/metrics</a></li>
+<li><a href="/-/healthy">/-/healthy</a></li>
+<li><a href="/-/ready">/-/ready</a></li>
+{probes}</ul>
+<h2>Hubs</h2>
+<ul>
+{hubs}</ul>
+</body>
+</html>
+"#,
+          name = env!("CARGO_PKG_NAME"),
+          description = env!("CARGO_PKG_DESCRIPTION"),
+          version = env!("CARGO_PKG_VERSION"),
+          repository = env!("CARGO_PKG_REPOSITORY")
+  )
+}
 
-      let devs = get_device_details(&he, get_device_ids(&he));
-      trace!("devs:{:#?}", devs);
+/// Latest hub state collected by the background poller and served on every scrape
+struct Snapshot {
+  hub_details:  Option<hub::HubDetails>,
+  hub_metrics:  Option<Vec<hub::HubMetric>>,
+  dev_inv:      Option<HashMap<String, hub::DeviceInventory>>,
+  devs:         Result<Vec<hub::Device>, anyhow::Error>,
+  /// Unparseable `NUMBER` values seen since startup per attribute and driver
+  parse_errors: HashMap<(String, String), u64>,
+  updated:      Instant,
+}
 
-      let m = build_metrics(&hub_metrics, devs, &dev_inv);
-      let response = Response::from_string(m);
-      let _ = request.respond(response);
+/// What and how often the background poller fetches from the hub
+struct PollOptions {
+  bulk:      bool,
+  workers:   usize,
+  interval:  Duration,
+  endpoints: Vec<hub::HubEndpoint>,
+}
+
+/// Refreshes the shared snapshot every `interval`, so scrapes never wait on the hub.
+/// Until the hub client could be built, for instance while a secret file is not mounted yet, it is retried on every interval.
+fn poll(h: &config::HubConfig, popts: &PollOptions, snapshot: &RwLock<Option<Snapshot>>, mappings: &RwLock<mapping::Mappings>, ready: &AtomicBool) {
+  let mut parse_errors: HashMap<(String, String), u64> = HashMap::new();
+  let mut he: Option<hub::HubInfo> = None;
+
+  loop {
+    let started = Instant::now();
+    mapping::reload(mappings);
+
+    match he.as_mut() {
+      Some(he) => refresh_credentials(he, h),
+      None => he = connect(h, popts.workers),
     }
-  } else {
-    error!("Starting web server with listener {:?}", listener);
+
+    match he.as_mut() {
+      Some(he) => {
+        // The mappings are only locked once the hub answered, so a slow hub does not hold off a reload and the scrapes waiting behind it
+        let mut snap = fetch(he, popts);
+        count_parse_errors(&mut snap, &mappings.read().unwrap(), &mut parse_errors);
+        if snap.devs.is_ok() && !ready.swap(true, Ordering::Relaxed) {
+          info!("first successful poll of hub {:?}, ready", h.name);
+        }
+        *snapshot.write().unwrap() = Some(snap);
+        info!("snapshot refreshed in {:?}", started.elapsed());
+      },
+      None => warn!("hub {:?} is not connected, retrying in {:?}", h.name, popts.interval),
+    }
+
+    thread::sleep(popts.interval.saturating_sub(started.elapsed()));
   }
 }
 
-fn build_metrics(hub_metrics: &Option<HashMap<String, String>>, devs: Result<Vec<hub::Device>, anyhow::Error>, dev_inv: &Option<HashMap<String, hub::DeviceInventory>>) -> String {
-  let mut metrics: String = "".to_owned();
+/// Fetches the hub state
+fn fetch(he: &mut hub::HubInfo, popts: &PollOptions) -> Snapshot {
+  let hub_details = get_hub_details(he);
+  trace!("hub_details:{:#?}", hub_details);
 
-  if let Some(d) = dev_inv {
-    if let Some(d) = d.iter().next() {
-      for (m, v) in hub_metrics.as_ref().unwrap() {
-        let m = &format!("hub_{metric}{{hub_name=\"{hub_name}\",hub_location_name=\"{hub_location_name}\"}} {val}\n", metric = m.to_case(Case::Snake), hub_name = d.1.hub_name, hub_location_name = d.1.location_name, val = v);
-        metrics.push_str(m);
+  let hub_metrics = get_hub_metrics(he, &popts.endpoints);
+  trace!("hub_metrics:{:#?}", hub_metrics);
+
+  let dev_inv = get_device_inventory(he);
+  trace!("dev_inv:{:#?}", dev_inv);
+
+  let devs = get_devices(he, popts.bulk, popts.workers);
+  trace!("devs:{:#?}", devs);
+
+  Snapshot { hub_details,
+             hub_metrics,
+             dev_inv,
+             devs,
+             parse_errors: HashMap::new(),
+             updated: Instant::now() }
+}
+
+/// Counts the unparseable values of the snapshot devices into `parse_errors` and stores the totals in the snapshot
+fn count_parse_errors(snap: &mut Snapshot, mappings: &mapping::Mappings, parse_errors: &mut HashMap<(String, String), u64>) {
+  if let Ok(devs) = &snap.devs {
+    for d in devs.iter() {
+      for a in d.attributes.iter().filter(|a| a.is_unparseable(&d.r#type, mappings)) {
+        debug!("Device ID: {:?} attribute {:?} value {:?} is not a number", d.id, a.name, a.current_value);
+        *parse_errors.entry((a.name.clone(), d.r#type.clone())).or_default() += 1;
       }
     }
   }
+  snap.parse_errors = parse_errors.clone();
+}
+
+/// Builds the client of a hub, logging into the hub in detailed mode
+fn connect(h: &config::HubConfig, workers: usize) -> Option<hub::HubInfo<'_>> {
+  let (api_access_token, auth_pwd) = match (secret::read(Some(&h.api_token), h.api_token_file.as_deref()), secret::read(h.auth_pwd.as_deref(), h.auth_pwd_file.as_deref())) {
+    (Ok(token), Ok(pwd)) => (token, pwd),
+    (Err(e), _) | (_, Err(e)) => {
+      error!("Reading the credentials of hub {:?}: {:?}", h.name, e);
+      return None;
+    },
+  };
+  let mut he = hub::HubInfo { ip: Some(&h.ip),
+                              auth_usr: h.auth_usr.as_deref(),
+                              auth_pwd,
+                              api_id: Some(&h.app_id),
+                              api_access_token,
+                              client: None,
+                              api_client: None };
+  match build_api_client(workers) {
+    Ok(c) => he.api_client = Some(c),
+    Err(e) => {
+      error!("Building API client for hub {:?}: {:?}", h.name, e);
+      return None;
+    },
+  }
+
+  if h.details {
+    info!("detailed mode is turned on for hub {:?}", h.name);
+    get_log(&mut he);
+  }
+
+  Some(he)
+}
+
+/// Re-reads the credential files of a hub, logging in again when the password was rotated
+fn refresh_credentials(he: &mut hub::HubInfo, h: &config::HubConfig) {
+  if let Some(f) = &h.api_token_file {
+    match secret::read(None, Some(f)) {
+      Ok(token) if token != he.api_access_token => {
+        info!("API token of hub {:?} rotated", h.name);
+        he.api_access_token = token;
+      },
+      Ok(_) => {},
+      Err(e) => error!("{:?}", e),
+    }
+  }
+
+  if let Some(f) = &h.auth_pwd_file {
+    match secret::read(None, Some(f)) {
+      Ok(pwd) if pwd != he.auth_pwd => {
+        info!("password of hub {:?} rotated", h.name);
+        he.auth_pwd = pwd;
+        if h.details {
+          get_log(he);
+        }
+      },
+      Ok(_) => {},
+      Err(e) => error!("{:?}", e),
+    }
+  }
+}
+
+/// Labels added to every series of a hub
+fn hub_labels(h: &config::HubConfig) -> Vec<(String, String)> { std::iter::once(("hub".to_string(), h.name.clone())).chain(h.labels.clone()).collect() }
+
+/// Scrapes the hub given by the `target` and `module` query parameters of a `/probe` request
+fn probe(url: &str, config: &config::Config, popts: &PollOptions, opts: &metrics::Options, mappings: &RwLock<mapping::Mappings>) -> Result<metrics::Metrics> {
+  let started = Instant::now();
+  let url = reqwest::Url::parse(&format!("http://localhost{url}")).map_err(|e| anyhow!("invalid probe url: {:?}", e))?;
+  let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+
+  let target = param("target").filter(|t| !t.is_empty()).ok_or_else(|| anyhow!("target parameter is missing"))?;
+  let module = param("module").unwrap_or_else(|| "default".to_string());
+  let h = config.module.get(&module).ok_or_else(|| anyhow!("unknown module {:?}", module))?.hub(&target);
+
+  let mut he = connect(&h, popts.workers).ok_or_else(|| anyhow!("connecting to hub {:?} failed", target))?;
+  let mut snap = fetch(&mut he, popts);
+  mapping::reload(mappings);
+  let mappings = mappings.read().unwrap();
+  count_parse_errors(&mut snap, &mappings, &mut HashMap::new());
+
+  let mut metrics = metrics::Metrics::new(&opts.namespace);
+  metrics.set_const_labels(hub_labels(&h));
+  build_metrics(&mut metrics, &snap, opts, &mappings);
+
+  metrics.set_const_labels(vec![]);
+  let name = metrics.family_name("probe_duration_seconds");
+  metrics.add(&name, "Seconds the probe of the hub took", metrics::MetricType::Gauge, vec![], &started.elapsed().as_secs_f64().to_string());
+  metrics.set_unit(&name, "seconds");
+
+  Ok(metrics)
+}
+
+/// Adds the series of a hub snapshot, the hub labels are expected to be set as const labels of `metrics`
+fn build_metrics(metrics: &mut metrics::Metrics, snap: &Snapshot, opts: &metrics::Options, mappings: &mapping::Mappings) {
+  let (hub_metrics, devs, dev_inv) = (&snap.hub_metrics, &snap.devs, &snap.dev_inv);
+
+  metrics.add("hubitat_exporter_snapshot_age_seconds", "Seconds since the last hub poll", metrics::MetricType::Gauge, vec![], &snap.updated.elapsed().as_secs_f64().to_string());
+  metrics.set_unit("hubitat_exporter_snapshot_age_seconds", "seconds");
+
+  add_hub_up(metrics, devs.is_ok());
+
+  // The hub identity comes from the hub itself, the inventory is only a fallback for the firmwares without the details endpoint
+  let (hub_name, hub_location_name) = match (&snap.hub_details, dev_inv.as_ref().and_then(|d| d.values().next())) {
+    (Some(h), _) => (h.name.as_str(), h.location_name.as_str()),
+    (None, Some(d)) => (d.hub_name.as_str(), d.location_name.as_str()),
+    (None, None) => ("", ""),
+  };
+  let hub_labels = [("hub_name", hub_name), ("hub_location_name", hub_location_name)];
+
+  if let Some(h) = &snap.hub_details {
+    let name = metrics.family_name("hub_info");
+    metrics.add(&name, "Hubitat hub information", metrics::MetricType::Gauge, vec![("hub_name", &h.name), ("hub_location_name", &h.location_name), ("version", &h.version), ("hardware_id", &h.hardware_id)], "1");
+  }
+
+  if let Some(hm) = hub_metrics {
+    add_hub_metrics(metrics, hm, &hub_labels, opts.info_max_length);
+  }
+
+  if let Some(d) = dev_inv {
+    add_inventory(metrics, d, opts, mappings);
+  }
 
   if let Ok(dev_details) = devs {
-    for i in dev_details.iter() {
-      if let Some(d) = dev_inv {
-        match d.get(&i.id) {
-          Some(d) => {
-            // Detailed mode without the Device Inventory
-            for a in i.attributes.iter() {
-              if let Some(v) = a.get_numeric_value() {
-                let m = &format!("{metric}{{hub_name=\"{hub_name}\",hub_location_name=\"{hub_location_name}\",device_network_id=\"{device_network_id}\",device_driver_type=\"{device_driver_type}\",device_driver=\"{device_driver}\",device_name=\"{device_name}\",device_label=\"{device_label}\"}} {val}\n", metric = a.name.to_case(Case::Snake), hub_name = d.hub_name, hub_location_name = d.location_name, device_network_id = d.device_network_id, device_driver_type = i.r#type, device_driver = d.device_type_name, device_name = i.name, device_label = i.label, val = v);
-                metrics.push_str(m);
+    for i in dev_details.iter().filter(|i| opts.devices.is_included(&i.id, &i.label, &i.name, &i.r#type)) {
+      let labels: Vec<(&str, &str)> = match dev_inv {
+        Some(inv) => {
+          match inv.get(&i.id) {
+            // Detailed mode with the Device Inventory
+            Some(d) if opts.suppress_stale && is_stale(d, opts, mappings) == Some(true) => {
+              debug!("Device ID: {:?} is stale, skipping its attributes", &i.id);
+              continue;
+            },
+            Some(d) => vec![("hub_name", &d.hub_name), ("hub_location_name", &d.location_name), ("device_network_id", &d.device_network_id), ("device_driver_type", &i.r#type), ("device_driver", &d.device_type_name), ("device_name", &i.name), ("device_label", &i.label), ("parent_device_id", &d.parent_device_id), ("parent_device_label", parent_label(d, inv))],
+            _ => {
+              if !&i.id.is_empty() {
+                warn!("Device ID: {:?} not found", &i.id);
               }
-            }
-          },
-          _ => {
-            if !&i.id.is_empty() {
-              warn!("Device ID: {:?} not found", &i.id);
-            }
-          },
-        }
-      } else {
-        // Simple mode without the Device Inventory
-        for a in i.attributes.iter() {
-          if let Some(v) = a.get_numeric_value() {
-            let m = &format!("{metric}{{device_name=\"{device_name}\",device_label=\"{device_label}\",device_driver_type=\"{device_driver_type}\"}} {val}\n", metric = a.name.to_case(Case::Snake), device_name = i.name, device_label = i.label, device_driver_type = i.r#type, val = v);
-            metrics.push_str(m);
+              continue;
+            },
           }
+        },
+        // Simple mode without the Device Inventory
+        None => vec![("device_name", &i.name), ("device_label", &i.label), ("device_driver_type", &i.r#type)],
+      };
+
+      add_capabilities(metrics, i, &labels);
+
+      for a in i.attributes.iter() {
+        if a.data_type != "NUMBER" && opts.is_info_attribute(&a.name) {
+          add_attribute_info(metrics, opts, a, &labels);
+        }
+
+        if opts.enum_states && !a.values.is_empty() {
+          add_attribute_states(metrics, opts, a, &labels);
+        } else if let Some((v, unit)) = opts.normalize_units.then(|| a.get_normalized_value(&i.r#type, mappings)).flatten() {
+          add_attribute(metrics, opts, a, &labels, &v.to_string(), Some(unit));
+        } else if let Some(v) = a.get_numeric_value(&i.r#type, mappings) {
+          add_attribute(metrics, opts, a, &labels, &v.to_string(), None);
         }
       }
     }
   }
 
-  metrics
+  let mut parse_errors: Vec<(&(String, String), &u64)> = snap.parse_errors.iter().collect();
+  parse_errors.sort();
+  for ((attribute, driver), n) in parse_errors {
+    let name = metrics.family_name("attribute_parse_errors_total");
+    metrics.add(&name, "Device attribute values that could not be parsed as a number", metrics::MetricType::Counter, vec![("attribute", attribute), ("device_driver_type", driver)], &n.to_string());
+  }
+}
+
+fn add_hub_up(metrics: &mut metrics::Metrics, up: bool) {
+  let name = metrics.family_name("hub_up");
+  metrics.add(&name, "Whether the last poll of the hub devices succeeded", metrics::MetricType::Gauge, vec![], if up { "1" } else { "0" });
+}
+
+/// Adds the values polled from the hub endpoints, the state and info values longer than `max_length` chars are skipped
+fn add_hub_metrics(metrics: &mut metrics::Metrics, hub_metrics: &[hub::HubMetric], labels: &[(&str, &str)], max_length: usize) {
+  let mut hm: Vec<&hub::HubMetric> = hub_metrics.iter().collect();
+  hm.sort_by(|a, b| a.metric.cmp(&b.metric));
+
+  for m in hm {
+    let help = format!("Hubitat hub {}", m.metric.to_case(Case::Lower));
+    let mut labels = labels.to_vec();
+    match m.kind {
+      hub::HubValueKind::Number => {
+        let Some(v) = hub::parse_numeric(&m.value) else {
+          warn!("hub metric {:?} value {:?} is not a number", m.metric, m.value);
+          continue;
+        };
+        let name = metrics.family_name(&format!("hub_{}", m.metric));
+        metrics.add(&name, &help, metrics::MetricType::Gauge, labels, &v.to_string());
+      },
+      hub::HubValueKind::State | hub::HubValueKind::Info if m.value.trim().chars().count() > max_length => {
+        warn!("hub metric {:?} value longer than {} chars, skipping", m.metric, max_length);
+      },
+      hub::HubValueKind::State => {
+        let name = metrics.family_name(&format!("hub_{}", m.metric));
+        labels.push(("state", m.value.trim()));
+        metrics.add(&name, &help, metrics::MetricType::Gauge, labels, "1");
+      },
+      hub::HubValueKind::Info => {
+        let name = metrics.family_name(&format!("hub_{}_info", m.metric));
+        labels.push(("value", m.value.trim()));
+        metrics.add(&name, &help, metrics::MetricType::Gauge, labels, "1");
+      },
+    }
+  }
+}
+
+/// Seconds since the last activity of a device
+fn last_activity_age(d: &hub::DeviceInventory) -> Option<f64> {
+  let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok()?.as_secs_f64();
+  d.last_activity_timestamp().map(|ts| (now - ts).max(0.0))
+}
+
+/// Whether the device has been inactive longer than its threshold, `None` when there is no threshold or last activity
+fn is_stale(d: &hub::DeviceInventory, opts: &metrics::Options, mappings: &mapping::Mappings) -> Option<bool> {
+  let threshold = mappings.stale_threshold(&d.id, &d.label, &d.device_type_name).unwrap_or(opts.stale_threshold);
+  if threshold == 0 {
+    return None;
+  }
+  last_activity_age(d).map(|age| age > threshold as f64)
+}
+
+/// Label of the parent of a child device, empty for the devices without a parent
+fn parent_label<'a>(d: &hub::DeviceInventory, dev_inv: &'a HashMap<String, hub::DeviceInventory>) -> &'a str { dev_inv.get(&d.parent_device_id).map(|p| p.label.as_str()).unwrap_or_default() }
+
+/// Adds the info, disabled, status, last activity and stale series of every device in the inventory
+fn add_inventory(metrics: &mut metrics::Metrics, dev_inv: &HashMap<String, hub::DeviceInventory>, opts: &metrics::Options, mappings: &mapping::Mappings) {
+  let mut inv: Vec<&hub::DeviceInventory> = dev_inv.values().collect();
+  inv.sort_by_key(|d| (d.id.parse::<u64>().unwrap_or(u64::MAX), d.id.clone()));
+
+  let (info, disabled, status, last_activity) = (metrics.family_name("device_info"), metrics.family_name("device_disabled"), metrics.family_name("device_status"), metrics.family_name("device_last_activity_timestamp_seconds"));
+  let (age, stale, parent) = (metrics.family_name("device_last_activity_age_seconds"), metrics.family_name("device_stale"), metrics.family_name("device_parent"));
+  for d in inv.into_iter().filter(|d| opts.devices.is_included(&d.id, &d.label, &d.name, &d.device_type_name)) {
+    let labels = vec![("hub_name", d.hub_name.as_str()), ("hub_location_name", &d.location_name), ("device_id", &d.id), ("device_network_id", &d.device_network_id), ("device_driver", &d.device_type_name), ("device_name", &d.name), ("device_label", &d.label)];
+
+    let mut info_labels = labels.clone();
+    info_labels.extend([("zigbee_id", d.zigbee_id.as_str()), ("mesh_enabled", &d.mesh_enabled), ("is_component", &d.is_component), ("parent_device_id", &d.parent_device_id), ("linked_device", &d.linked_device)]);
+    metrics.add(&info, "Hubitat device inventory information", metrics::MetricType::Gauge, info_labels, "1");
+
+    if !d.parent_device_id.is_empty() {
+      let parent_labels = vec![("hub_name", d.hub_name.as_str()), ("hub_location_name", &d.location_name), ("device_id", &d.id), ("device_label", &d.label), ("parent_device_id", &d.parent_device_id), ("parent_device_label", parent_label(d, dev_inv))];
+      metrics.add(&parent, "Hubitat child device relationship to its parent device", metrics::MetricType::Gauge, parent_labels, "1");
+    }
+
+    metrics.add(&disabled, "Hubitat device is disabled", metrics::MetricType::Gauge, labels.clone(), if d.is_disabled() { "1" } else { "0" });
+
+    let mut status_labels = labels.clone();
+    status_labels.push(("status", &d.status));
+    metrics.add(&status, "Hubitat device status reported by the hub", metrics::MetricType::Gauge, status_labels, "1");
+
+    if let Some(ts) = d.last_activity_timestamp() {
+      metrics.add(&last_activity, "Hubitat device last activity time", metrics::MetricType::Gauge, labels.clone(), &ts.to_string());
+      metrics.set_unit(&last_activity, "seconds");
+    }
+    if let Some(a) = last_activity_age(d) {
+      metrics.add(&age, "Seconds since the Hubitat device last activity", metrics::MetricType::Gauge, labels.clone(), &a.to_string());
+      metrics.set_unit(&age, "seconds");
+    }
+    if let Some(s) = is_stale(d, opts, mappings) {
+      metrics.add(&stale, "Hubitat device inactive for longer than its stale threshold", metrics::MetricType::Gauge, labels, if s { "1" } else { "0" });
+    }
+  }
 }
 
-fn get_log(he: &mut hub::HubInfo, app: &ArgMatches) {
+/// Adds one series per capability and per command supported by the device driver
+fn add_capabilities(metrics: &mut metrics::Metrics, dev: &hub::Device, labels: &[(&str, &str)]) {
+  let (capability, command) = (metrics.family_name("device_capability"), metrics.family_name("device_command"));
+
+  let mut caps: Vec<&str> = dev.capability_names().collect();
+  caps.sort_unstable();
+  caps.dedup();
+  for c in caps {
+    let mut labels = labels.to_vec();
+    labels.push(("capability", c));
+    metrics.add(&capability, "Hubitat device capability supported by its driver", metrics::MetricType::Gauge, labels, "1");
+  }
+
+  let mut cmds: Vec<&str> = dev.command_names().collect();
+  cmds.sort_unstable();
+  cmds.dedup();
+  for c in cmds {
+    let mut labels = labels.to_vec();
+    labels.push(("command", c));
+    metrics.add(&command, "Hubitat device command supported by its driver", metrics::MetricType::Gauge, labels, "1");
+  }
+}
+
+/// Adds an attribute sample either as its own metric family or as the `attribute` label of the shared device attribute family.
+/// A normalized `unit` is the metric name suffix, or a `unit` label in the attribute label mode.
+fn add_attribute(metrics: &mut metrics::Metrics, opts: &metrics::Options, a: &hub::DeviceAttribute, labels: &[(&str, &str)], v: &str, unit: Option<&str>) {
+  let mut labels = labels.to_vec();
+  if opts.attribute_label {
+    let name = metrics.family_name("device_attribute_value");
+    labels.push(("attribute", &a.name));
+    if let Some(unit) = unit {
+      labels.push(("unit", unit));
+    }
+    metrics.add(&name, "Hubitat device attribute value", metrics::MetricType::Gauge, labels, v);
+  } else if let Some(unit) = unit {
+    let name = metrics.family_name(&format!("{attr}_{unit}", attr = a.name));
+    metrics.add(&name, &format!("{help} in {unit}", help = a.help()), a.metric_type(), labels, v);
+    metrics.set_unit(&name, unit);
+  } else {
+    let name = metrics.family_name(&a.name);
+    metrics.add(&name, &a.help(), a.metric_type(), labels, v);
+  }
+}
+
+/// Adds one StateSet style sample per allowed value of an enum attribute, the current value is 1 and the others 0
+fn add_attribute_states(metrics: &mut metrics::Metrics, opts: &metrics::Options, a: &hub::DeviceAttribute, labels: &[(&str, &str)]) {
+  let (name, help, mut labels) = if opts.attribute_label {
+    let mut labels = labels.to_vec();
+    labels.push(("attribute", &a.name));
+    (metrics.family_name("device_attribute_state"), "Hubitat device attribute state".to_string(), labels)
+  } else {
+    // Own family, the attributes of the devices without allowed values keep the mapped number under the attribute name
+    (metrics.family_name(&format!("{attr}_state", attr = a.name)), format!("{help} state", help = a.help()), labels.to_vec())
+  };
+
+  let mut states: Vec<&str> = a.values.iter().map(|v| v.as_str()).collect();
+  if !a.current_value.is_empty() && !states.iter().any(|s| s.eq_ignore_ascii_case(&a.current_value)) {
+    states.push(&a.current_value);
+  }
+
+  labels.push(("state", ""));
+  for s in states {
+    labels.last_mut().unwrap().1 = s;
+    metrics.add(&name, &help, metrics::MetricType::Gauge, labels.clone(), if s.eq_ignore_ascii_case(&a.current_value) { "1" } else { "0" });
+  }
+}
+
+/// Adds a non numeric attribute as an info sample carrying its value as a label
+fn add_attribute_info(metrics: &mut metrics::Metrics, opts: &metrics::Options, a: &hub::DeviceAttribute, labels: &[(&str, &str)]) {
+  if a.current_value.is_empty() {
+    return;
+  }
+  if a.current_value.chars().count() > opts.info_max_length {
+    debug!("attribute {:?} value longer than {} chars, skipping info metric", a.name, opts.info_max_length);
+    return;
+  }
+
+  let name = metrics.family_name("device_attribute_info");
+  let mut labels = labels.to_vec();
+  labels.push(("attribute", &a.name));
+  labels.push(("value", &a.current_value));
+  metrics.add(&name, "Hubitat device non numeric attribute value", metrics::MetricType::Gauge, labels, "1");
+}
+
+fn get_log(he: &mut hub::HubInfo) {
   if let Ok(c) = reqwest::blocking::Client::builder().user_agent(env!("CARGO_PKG_NAME")).cookie_store(true).danger_accept_invalid_certs(true).connection_verbose(true).build() {
     let req_url = format!("http://{he_ip}/login", he_ip = he.ip.unwrap());
 
-    let req: RequestBuilder = if app.get_one::<String>("he_auth_usr").is_some() && app.get_one::<String>("he_auth_pwd").is_some() {
-      debug!("Auth on {:?}/{:?}", app.get_one::<String>("he_auth_usr").unwrap(), app.get_one::<String>("he_auth_pwd").unwrap());
+    let req: RequestBuilder = if let (Some(usr), Some(pwd)) = (he.auth_usr, he.auth_pwd.as_deref()) {
+      debug!("Auth on {:?}", usr);
 
       let mut params = HashMap::new();
-      params.insert("username", app.get_one::<String>("he_auth_usr").unwrap());
-      params.insert("password", app.get_one::<String>("he_auth_pwd").unwrap());
+      params.insert("username", usr);
+      params.insert("password", pwd);
       c.post(req_url).form(&params)
     } else {
       c.post(req_url)
@@ -151,85 +719,94 @@ fn get_log(he: &mut hub::HubInfo, app: &ArgMatches) {
   }
 }
 
-fn get_hub_metrics(he: &mut hub::HubInfo) -> Option<HashMap<String, String>> {
-  let mut hub_metrics = HashMap::new();
+fn get_hub_metrics(he: &mut hub::HubInfo, endpoints: &[hub::HubEndpoint]) -> Option<Vec<hub::HubMetric>> {
+  he.client.as_ref()?;
 
-  if let Some(c) = &he.client {
-    let req_url = format!("http://{he_ip}:8080/hub/advanced/cpu1min", he_ip = he.ip.unwrap());
-    match c.get(req_url).send() {
-      Ok(r) => {
-        if r.status().is_success() {
-          debug!("resp:{:#?}", r);
-          if let Ok(body) = r.text() {
-            hub_metrics.insert("cpu_load_1min".to_string(), body);
-          }
-        } else {
-          error!("request get failed: {:?}", r);
-        }
+  let mut hub_metrics = Vec::with_capacity(endpoints.len());
+  for e in endpoints {
+    match get_hub_value(he, &e.path) {
+      Ok(HubValue::Value(value)) => {
+        hub_metrics.push(hub::HubMetric { metric: e.metric.clone(),
+                                          kind: e.kind,
+                                          value })
       },
-      Err(e) => {
-        error!("request get failed: {:?}", e);
+      Ok(HubValue::NotFound) => {},
+      // Same as the device inventory, an expired session gets the login page, the remaining endpoints are read on the next poll
+      Ok(HubValue::LoginPage) => {
+        get_log(he);
+        error!("hub endpoint {} returned the login page, logged in again", e.path);
+        break;
       },
+      Err(e) => error!("{:?}", e),
     }
+  }
 
-    let req_url = format!("http://{he_ip}:8080/hub/advanced/freeOSMemory", he_ip = he.ip.unwrap());
-    match c.get(req_url).send() {
-      Ok(r) => {
-        if r.status().is_success() {
-          debug!("resp:{:#?}", r);
-          if let Ok(body) = r.text() {
-            hub_metrics.insert("free_os_memory".to_string(), body);
-          }
-        } else {
-          error!("request get failed: {:?}", r);
-        }
-      },
-      Err(e) => {
-        error!("request get failed: {:?}", e);
-      },
-    }
+  Some(hub_metrics)
+}
 
-    let req_url = format!("http://{he_ip}:8080/hub/advanced/databaseSize", he_ip = he.ip.unwrap());
-    match c.get(req_url).send() {
-      Ok(r) => {
-        if r.status().is_success() {
-          debug!("resp:{:#?}", r);
-          if let Ok(body) = r.text() {
-            hub_metrics.insert("database_size".to_string(), body);
-          }
-        } else {
-          error!("request get failed: {:?}", r);
-        }
-      },
-      Err(e) => {
-        error!("request get failed: {:?}", e);
-      },
-    }
+/// Body of a hub endpoint
+enum HubValue {
+  Value(String),
+  /// The hub firmware does not provide the endpoint
+  NotFound,
+  /// The hub answered with its login page, the session expired
+  LoginPage,
+}
 
-    let req_url = format!("http://{he_ip}:8080/hub/advanced/internalTempCelsius", he_ip = he.ip.unwrap());
-    match c.get(req_url).send() {
-      Ok(r) => {
-        if r.status().is_success() {
-          debug!("resp:{:#?}", r);
-          if let Ok(body) = r.text() {
-            hub_metrics.insert("temperature".to_string(), body);
-          }
-        } else {
-          error!("request get failed: {:?}", r);
+/// Fetches the body of a hub endpoint
+fn get_hub_value(he: &hub::HubInfo, path: &str) -> Result<HubValue, anyhow::Error> {
+  let c = he.client.as_ref().ok_or_else(|| anyhow!("hub client not initialized"))?;
+  let req_url = format!("http://{he_ip}:8080{path}", he_ip = he.ip.unwrap());
+  match c.get(req_url).send() {
+    Ok(r) => {
+      if r.status().is_success() {
+        debug!("resp:{:#?}", r);
+        if r.headers().get(reqwest::header::X_FRAME_OPTIONS).is_some() {
+          return Ok(HubValue::LoginPage);
         }
-      },
-      Err(e) => {
-        error!("request get failed: {:?}", e);
-      },
-    }
+        r.text().map(HubValue::Value).map_err(|e| anyhow!("reading {} failed: {:?}", path, e))
+      } else if r.status() == reqwest::StatusCode::NOT_FOUND {
+        debug!("hub endpoint {} not found, skipping", path);
+        Ok(HubValue::NotFound)
+      } else {
+        Err(anyhow!("request get failed: {:?}", r))
+      }
+    },
+    Err(e) => Err(anyhow!("request get failed: {:?}", e)),
+  }
+}
 
-    Some(hub_metrics)
-  } else {
-    None
+fn get_hub_details(he: &hub::HubInfo) -> Option<hub::HubDetails> {
+  let c = he.client.as_ref()?;
+  let req_url = format!("http://{he_ip}/hub/details/json", he_ip = he.ip.unwrap());
+
+  match c.get(req_url).send() {
+    Ok(r) => {
+      if r.status().is_success() {
+        debug!("resp:{:#?}", r);
+        match r.json::<hub::HubDetails>() {
+          Ok(h) => Some(h),
+          Err(e) => {
+            error!("json parsing failed: {:?}", e);
+            None
+          },
+        }
+      } else if r.status() == reqwest::StatusCode::NOT_FOUND {
+        debug!("hub details endpoint not found, falling back to the inventory");
+        None
+      } else {
+        error!("request get failed: {:?}", r);
+        None
+      }
+    },
+    Err(e) => {
+      error!("request get failed: {:?}", e);
+      None
+    },
   }
 }
 
-fn get_device_inventory(he: &mut hub::HubInfo, app: &ArgMatches) -> Option<HashMap<String, hub::DeviceInventory>> {
+fn get_device_inventory(he: &mut hub::HubInfo) -> Option<HashMap<String, hub::DeviceInventory>> {
   if let Some(c) = &he.client {
     let req_url = format!("http://{he_ip}/device/list/all/data", he_ip = he.ip.unwrap());
 
@@ -238,7 +815,7 @@ fn get_device_inventory(he: &mut hub::HubInfo, app: &ArgMatches) -> Option<HashM
         if r.status().is_success() {
           debug!("resp:{:#?}", r);
           if let Some(_h) = r.headers().get(reqwest::header::X_FRAME_OPTIONS) {
-            get_log(he, app);
+            get_log(he);
             error!("loging failed");
             return None;
           }
@@ -267,9 +844,43 @@ fn get_device_inventory(he: &mut hub::HubInfo, app: &ArgMatches) -> Option<HashM
   }
 }
 
+fn build_api_client(workers: usize) -> Result<reqwest::blocking::Client, anyhow::Error> { reqwest::blocking::Client::builder().user_agent(env!("CARGO_PKG_NAME")).danger_accept_invalid_certs(true).connection_verbose(true).pool_max_idle_per_host(workers.max(1)).timeout(Duration::from_secs(10)).build().map_err(|e| anyhow!("building client failed: {:?}", e)) }
+
+/// Fetches all the devices, using the bulk endpoint when enabled and falling back to one request per device
+fn get_devices(he: &hub::HubInfo, bulk: bool, workers: usize) -> Result<Vec<hub::Device>, anyhow::Error> {
+  if bulk {
+    match get_device_details_bulk(he) {
+      Ok(devs) => return Ok(devs),
+      Err(e) => warn!("bulk device fetch failed, falling back to per device requests: {:?}", e),
+    }
+  }
+
+  get_device_details(he, get_device_ids(he), workers)
+}
+
+fn get_device_details_bulk(he: &hub::HubInfo) -> Result<Vec<hub::Device>, anyhow::Error> {
+  let req_url = format!("http://{he_ip}/apps/api/{he_api_id}/devices/all?access_token={he_api_token}", he_ip = he.ip.unwrap(), he_api_id = he.api_id.unwrap(), he_api_token = he.api_access_token.as_deref().unwrap());
+  let client = he.api_client.as_ref().ok_or_else(|| anyhow!("api client not initialized"))?;
+
+  match client.get(req_url).send() {
+    Ok(r) => {
+      debug!("resp:{:#?}", r);
+      if r.status().is_success() {
+        match r.json::<Vec<hub::DeviceAll>>() {
+          Ok(d) => Ok(d.into_iter().map(hub::Device::from).collect()),
+          Err(e) => Err(anyhow!("json parsing failed: {:?}", e)),
+        }
+      } else {
+        Err(anyhow!("request get failed: {:?}", r))
+      }
+    },
+    Err(e) => Err(anyhow!("request get failed: {:?}", e)),
+  }
+}
+
 fn get_device_ids(he: &hub::HubInfo) -> Result<Vec<u32>, anyhow::Error> {
-  let req_url = format!("http://{he_ip}/apps/api/{he_api_id}/devices?access_token={he_api_token}", he_ip = he.ip.unwrap(), he_api_id = he.api_id.unwrap(), he_api_token = he.api_access_token.unwrap());
-  let client = reqwest::blocking::Client::builder().user_agent(env!("CARGO_PKG_NAME")).danger_accept_invalid_certs(true).connection_verbose(true).build().expect("Error building client");
+  let req_url = format!("http://{he_ip}/apps/api/{he_api_id}/devices?access_token={he_api_token}", he_ip = he.ip.unwrap(), he_api_id = he.api_id.unwrap(), he_api_token = he.api_access_token.as_deref().unwrap());
+  let client = he.api_client.as_ref().ok_or_else(|| anyhow!("api client not initialized"))?;
 
   match client.get(req_url).send() {
     Ok(r) => {
@@ -290,34 +901,49 @@ fn get_device_ids(he: &hub::HubInfo) -> Result<Vec<u32>, anyhow::Error> {
   }
 }
 
-fn get_device_details(he: &hub::HubInfo, ids: Result<Vec<u32>, anyhow::Error>) -> Result<Vec<hub::Device>, anyhow::Error> {
-  let mut devs: Vec<hub::Device> = vec![hub::Device::default()];
+fn get_device_detail(he: &hub::HubInfo, client: &reqwest::blocking::Client, dev_id: u32) -> Result<hub::Device, anyhow::Error> {
+  let req_url = format!("http://{he_ip}/apps/api/{he_api_id}/devices/{dev_id}?access_token={he_api_token}", he_ip = he.ip.unwrap(), he_api_id = he.api_id.unwrap(), he_api_token = he.api_access_token.as_deref().unwrap(), dev_id = dev_id);
 
-  if let Ok(dev_ids) = ids {
-    for i in dev_ids.iter() {
-      let req_url = format!("http://{he_ip}/apps/api/{he_api_id}/devices/{dev_id}?access_token={he_api_token}", he_ip = he.ip.unwrap(), he_api_id = he.api_id.unwrap(), he_api_token = he.api_access_token.unwrap(), dev_id = i);
-      let client = reqwest::blocking::Client::builder().user_agent(env!("CARGO_PKG_NAME")).danger_accept_invalid_certs(true).connection_verbose(true).build().expect("Error building client");
-
-      match client.get(req_url).send() {
-        Ok(r) => {
-          debug!("resp:{:#?}", r);
-          if r.status().is_success() {
-            match r.json::<hub::Device>() {
-              Ok(d) => devs.push(d),
-              Err(e) => {
-                error!("json parsing failed: {:?}", e);
-              },
-            }
-          } else {
-            return Err(anyhow!("request get failed: {:?}", r));
-          }
-        },
-        Err(e) => {
-          return Err(anyhow!("request get failed: {:?}", e));
-        },
+  match client.get(req_url).send() {
+    Ok(r) => {
+      debug!("resp:{:#?}", r);
+      if r.status().is_success() {
+        r.json::<hub::Device>().map_err(|e| anyhow!("json parsing failed: {:?}", e))
+      } else {
+        Err(anyhow!("request get failed: {:?}", r))
       }
-    }
+    },
+    Err(e) => Err(anyhow!("request get failed: {:?}", e)),
   }
+}
+
+/// Fetches the details of every device using up to `workers` concurrent requests over the shared API client.
+/// A failing device is logged and skipped so it does not abort the whole batch.
+fn get_device_details(he: &hub::HubInfo, ids: Result<Vec<u32>, anyhow::Error>, workers: usize) -> Result<Vec<hub::Device>, anyhow::Error> {
+  let dev_ids = ids?;
+  let client = he.api_client.as_ref().ok_or_else(|| anyhow!("api client not initialized"))?;
+  let next = AtomicUsize::new(0);
+  let devs: Mutex<Vec<(usize, hub::Device)>> = Mutex::new(Vec::with_capacity(dev_ids.len()));
+
+  thread::scope(|s| {
+    for _ in 0..workers.clamp(1, dev_ids.len().max(1)) {
+      s.spawn(|| {
+         loop {
+           let idx = next.fetch_add(1, Ordering::Relaxed);
+           let Some(dev_id) = dev_ids.get(idx) else {
+             break;
+           };
+
+           match get_device_detail(he, client, *dev_id) {
+             Ok(d) => devs.lock().unwrap().push((idx, d)),
+             Err(e) => error!("Device ID: {} details failed: {:?}", dev_id, e),
+           }
+         }
+       });
+    }
+  });
 
-  Ok(devs)
+  let mut devs = devs.into_inner().unwrap();
+  devs.sort_by_key(|(idx, _)| *idx);
+  Ok(devs.into_iter().map(|(_, d)| d).collect())
 }
@@ -0,0 +1,110 @@
+use std::{collections::HashMap, fs, path::PathBuf, sync::RwLock, time::SystemTime};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// User defined string to number mapping of an attribute, optionally restricted to a driver
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ValueMapping {
+  pub attribute: String,
+  #[serde(default)]
+  pub driver:    Option<String>,
+  pub values:    HashMap<String, f64>,
+  #[serde(default)]
+  pub other:     Option<f64>,
+}
+
+impl ValueMapping {
+  pub fn value(&self, value: &str) -> Option<f64> { self.values.iter().find(|(v, _)| v.eq_ignore_ascii_case(value)).map(|(_, n)| *n) }
+}
+
+/// Unit of an attribute reported without one, optionally restricted to a driver
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UnitHint {
+  pub attribute: String,
+  #[serde(default)]
+  pub driver:    Option<String>,
+  pub unit:      String,
+}
+
+/// Seconds without activity after which a device is stale, for a device (ID or label) or a driver
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StaleThreshold {
+  #[serde(default)]
+  pub device:    Option<String>,
+  #[serde(default)]
+  pub driver:    Option<String>,
+  pub threshold: u64,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct MappingFile {
+  #[serde(default)]
+  mapping: Vec<ValueMapping>,
+  #[serde(default)]
+  unit:    Vec<UnitHint>,
+  #[serde(default)]
+  stale:   Vec<StaleThreshold>,
+}
+
+/// Attribute value mappings loaded from the user mapping file, they override and extend the built-in `hub::ENUM_MAPPINGS`
+#[derive(Debug, Default)]
+pub struct Mappings {
+  pub path:     Option<PathBuf>,
+  pub modified: Option<SystemTime>,
+  pub mappings: Vec<ValueMapping>,
+  pub units:    Vec<UnitHint>,
+  pub stale:    Vec<StaleThreshold>,
+}
+
+impl Mappings {
+  pub fn load(path: &str) -> Result<Mappings> {
+    let path = PathBuf::from(path);
+    let modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+    let file: MappingFile = crate::config::parse_file(&path).map_err(|e| anyhow!("loading mapping file failed: {:?}", e))?;
+
+    Ok(Mappings { path: Some(path),
+                  modified,
+                  mappings: file.mapping,
+                  units: file.unit,
+                  stale: file.stale })
+  }
+
+  /// Returns the mapping of `attribute`, a driver specific mapping takes precedence over a generic one
+  pub fn find(&self, attribute: &str, driver: &str) -> Option<&ValueMapping> {
+    let mut found = self.mappings.iter().filter(|m| m.attribute.eq_ignore_ascii_case(attribute));
+    found.clone().find(|m| m.driver.as_deref().is_some_and(|d| d.eq_ignore_ascii_case(driver))).or_else(|| found.find(|m| m.driver.is_none()))
+  }
+
+  /// Returns the unit hint of `attribute`, a driver specific hint takes precedence over a generic one
+  pub fn unit_hint(&self, attribute: &str, driver: &str) -> Option<&str> {
+    let mut found = self.units.iter().filter(|u| u.attribute.eq_ignore_ascii_case(attribute));
+    found.clone().find(|u| u.driver.as_deref().is_some_and(|d| d.eq_ignore_ascii_case(driver))).or_else(|| found.find(|u| u.driver.is_none())).map(|u| u.unit.as_str())
+  }
+
+  /// Returns the stale threshold of a device, a device threshold takes precedence over a driver one
+  pub fn stale_threshold(&self, device_id: &str, device_label: &str, driver: &str) -> Option<u64> { self.stale.iter().find(|s| s.device.as_deref().is_some_and(|d| d == device_id || d.eq_ignore_ascii_case(device_label))).or_else(|| self.stale.iter().find(|s| s.device.is_none() && s.driver.as_deref().is_some_and(|d| d.eq_ignore_ascii_case(driver)))).map(|s| s.threshold) }
+}
+
+/// Reloads the mapping file when it was modified since it was last loaded, on error the current mappings are kept
+pub fn reload(mappings: &RwLock<Mappings>) {
+  let (path, modified) = {
+    let m = mappings.read().unwrap();
+    match &m.path {
+      Some(p) => (p.clone(), m.modified),
+      None => return,
+    }
+  };
+
+  if fs::metadata(&path).and_then(|m| m.modified()).ok() == modified {
+    return;
+  }
+
+  match Mappings::load(&path.to_string_lossy()) {
+    Ok(m) => {
+      info!("reloaded {} mappings from {:?}", m.mappings.len(), path);
+      *mappings.write().unwrap() = m;
+    },
+    Err(e) => error!("{:?}", e),
+  }
+}
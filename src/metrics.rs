@@ -0,0 +1,352 @@
+use std::{collections::HashMap, fmt};
+
+use convert_case::{Case, Casing};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricType {
+  Gauge,
+  Counter,
+}
+
+impl fmt::Display for MetricType {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      MetricType::Gauge => write!(f, "gauge"),
+      MetricType::Counter => write!(f, "counter"),
+    }
+  }
+}
+
+/// Exposition format served to the scraper
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+  Prometheus,
+  OpenMetrics,
+}
+
+impl Format {
+  /// Picks the format from an `Accept` header, the media type with the highest `q` wins and OpenMetrics is preferred on a tie
+  pub fn negotiate(accept: Option<&str>) -> Format {
+    let (mut om, mut text) = (0.0_f64, 0.0_f64);
+    for media in accept.unwrap_or_default().split(',') {
+      let mut params = media.split(';').map(|p| p.trim());
+      let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+      let q = params.filter_map(|p| p.split_once('=')).find(|(k, _)| k.trim().eq_ignore_ascii_case("q")).map_or(Some(1.0), |(_, v)| v.trim().parse::<f64>().ok()).filter(|q| (0.0..=1.0).contains(q)).unwrap_or(0.0);
+      match media_type.as_str() {
+        "application/openmetrics-text" => om = om.max(q),
+        "text/plain" | "text/*" | "*/*" => text = text.max(q),
+        _ => {},
+      }
+    }
+
+    if om > 0.0 && om >= text {
+      Format::OpenMetrics
+    } else {
+      Format::Prometheus
+    }
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      Format::Prometheus => "text/plain; version=0.0.4; charset=utf-8",
+      Format::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Sample {
+  pub labels: Vec<(String, String)>,
+  pub value:  String,
+}
+
+#[derive(Debug)]
+pub struct Family {
+  pub name:    String,
+  pub help:    String,
+  pub r#type:  MetricType,
+  pub unit:    Option<String>,
+  pub samples: Vec<Sample>,
+}
+
+/// Settings controlling how the hub state is exposed as metrics
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+  /// Prefix of all the device and hub metric names, empty keeps the legacy unprefixed names
+  pub namespace:       String,
+  /// Expose all the device attributes as a single family with an `attribute` label
+  pub attribute_label: bool,
+  /// Expose the enum attributes as one series per allowed value instead of a mapped number
+  pub enum_states:     bool,
+  /// Non numeric attributes exposed as info metrics, `*` allows all of them
+  pub info_attributes: Vec<String>,
+  /// Longest attribute value exposed as an info metric label
+  pub info_max_length: usize,
+  /// Convert the known attributes to their base unit
+  pub normalize_units: bool,
+  /// Default seconds without activity after which a device is stale, 0 disables it
+  pub stale_threshold: u64,
+  /// Skip the attribute series of the stale devices
+  pub suppress_stale:  bool,
+  /// Devices to expose
+  pub devices:         crate::config::DeviceFilter,
+}
+
+impl Options {
+  pub fn is_info_attribute(&self, attribute: &str) -> bool { self.info_attributes.iter().any(|a| a == "*" || a.eq_ignore_ascii_case(attribute)) }
+}
+
+/// Collects samples grouped per metric family, so every family is written once with its `# HELP` and `# TYPE` metadata
+#[derive(Debug, Default)]
+pub struct Metrics {
+  namespace:    String,
+  families:     Vec<Family>,
+  index:        HashMap<String, usize>,
+  /// Family keys of the raw names that are not their own sanitized name, with the sanitized name they are rendered under when it is not taken
+  aliases:      HashMap<String, String>,
+  const_labels: Vec<(String, String)>,
+}
+
+/// Maps an arbitrary name to a valid `[a-zA-Z_:][a-zA-Z0-9_:]*` snake cased metric name
+pub fn sanitize_name(name: &str) -> String {
+  let mut out: String = name.to_case(Case::Snake).chars().map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' }).collect();
+  if out.is_empty() || out.starts_with(|c: char| c.is_ascii_digit()) {
+    out.insert(0, '_');
+  }
+  out
+}
+
+/// Escapes a label value as required by the text exposition format
+pub fn escape_label_value(v: &str) -> String { v.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n") }
+
+/// Escapes a `# HELP` text as required by the exposition `format`
+pub fn escape_help(v: &str, format: Format) -> String {
+  match format {
+    Format::Prometheus => v.replace('\\', "\\\\").replace('\n', "\\n"),
+    Format::OpenMetrics => escape_label_value(v),
+  }
+}
+
+/// FNV-1a, used to derive a stable suffix that is independent of the Rust version
+fn fnv1a(v: &str) -> u32 { v.bytes().fold(0x811c_9dc5_u32, |h, b| (h ^ b as u32).wrapping_mul(0x0100_0193)) }
+
+/// Checks that `ns` can prefix a metric name
+pub fn is_valid_namespace(ns: &str) -> bool { ns.chars().enumerate().all(|(i, c)| c.is_ascii_alphabetic() || c == '_' || c == ':' || (i > 0 && c.is_ascii_digit())) }
+
+impl Metrics {
+  pub fn new(namespace: &str) -> Self {
+    Metrics { namespace: namespace.to_string(),
+              ..Default::default() }
+  }
+
+  /// Returns the key of the family of `raw`, its namespaced and sanitized metric name when `raw` is already a valid snake case name.
+  /// Any other raw name, such as `someAttr` or `some-attr`, is keyed with a stable hash of `raw` appended and is rendered under the sanitized name
+  /// unless a raw name equal to it, or another raw name mapping to it, is also rendered. The names do not depend on the order the devices are seen in.
+  pub fn family_name(&mut self, raw: &str) -> String {
+    let sanitized = sanitize_name(raw);
+    let name = format!("{ns}{sanitized}", ns = self.namespace);
+    if sanitized == raw {
+      return name;
+    }
+
+    let key = format!("{name}_{hash:08x}", hash = fnv1a(raw));
+    self.aliases.entry(key.clone()).or_insert(name);
+    key
+  }
+
+  /// Sets the labels prepended to every sample added from now on, such as the `hub` of the samples. The labels of a sample take precedence.
+  pub fn set_const_labels(&mut self, labels: Vec<(String, String)>) { self.const_labels = labels; }
+
+  /// Adds a sample to the `name` family, creating the family on first use. The help and type of the first sample win.
+  pub fn add(&mut self, name: &str, help: &str, r#type: MetricType, labels: Vec<(&str, &str)>, value: &str) {
+    let idx = match self.index.get(name) {
+      Some(idx) => *idx,
+      None => {
+        self.families.push(Family { name: name.to_string(),
+                                    help: help.to_string(),
+                                    r#type,
+                                    unit: None,
+                                    samples: vec![] });
+        self.index.insert(name.to_string(), self.families.len() - 1);
+        self.families.len() - 1
+      },
+    };
+
+    // A label of the sample wins over a const label of the same name, duplicated label names would make the whole exposition invalid
+    let consts: Vec<(String, String)> = self.const_labels.iter().filter(|(k, _)| !labels.iter().any(|(l, _)| l == k)).cloned().collect();
+    let labels = consts.into_iter().chain(labels.into_iter().map(|(k, v)| (k.to_string(), v.to_string()))).collect();
+    self.families[idx].samples.push(Sample { labels,
+                                             value: value.to_string() });
+  }
+
+  /// Sets the OpenMetrics unit of the `name` family, OpenMetrics requires the family name to end with the unit
+  pub fn set_unit(&mut self, name: &str, unit: &str) {
+    if let Some(idx) = self.index.get(name) {
+      if self.aliases.get(name).map(|a| a.as_str()).unwrap_or(name).ends_with(&format!("_{unit}")) {
+        self.families[*idx].unit = Some(unit.to_string());
+      } else {
+        debug!("unit {:?} is not a suffix of {:?}, skipping", unit, name);
+      }
+    }
+  }
+
+  /// Returns the rendered name of a family, an aliased family keeps its hash suffix when its sanitized name is claimed more than once
+  fn rendered_name<'a>(&'a self, f: &'a Family, claims: &HashMap<&str, usize>) -> &'a str {
+    match self.aliases.get(&f.name) {
+      Some(name) if !self.index.contains_key(name) && claims.get(name.as_str()) == Some(&1) => name,
+      Some(name) => {
+        warn!("metric name collision on {:?}, using {:?}", name, f.name);
+        &f.name
+      },
+      None => &f.name,
+    }
+  }
+
+  pub fn render(&self, format: Format) -> String {
+    let mut out = String::new();
+
+    let mut claims: HashMap<&str, usize> = HashMap::new();
+    for name in self.families.iter().filter_map(|f| self.aliases.get(&f.name)) {
+      *claims.entry(name).or_default() += 1;
+    }
+
+    for f in self.families.iter() {
+      let rendered = self.rendered_name(f, &claims);
+      // OpenMetrics counters are described without the suffix while their samples carry `_total`
+      let (name, sample_name) = match (format, f.r#type) {
+        (Format::OpenMetrics, MetricType::Counter) => {
+          let name = rendered.strip_suffix("_total").unwrap_or(rendered);
+          (name.to_string(), format!("{name}_total"))
+        },
+        _ => (rendered.to_string(), rendered.to_string()),
+      };
+
+      out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {type}\n", help = escape_help(&f.help, format), type = f.r#type));
+      if let (Format::OpenMetrics, Some(unit)) = (format, f.unit.as_ref().filter(|u| rendered.ends_with(&format!("_{u}")))) {
+        out.push_str(&format!("# UNIT {name} {unit}\n"));
+      }
+
+      for s in f.samples.iter() {
+        if s.labels.is_empty() {
+          out.push_str(&format!("{sample_name} {val}\n", val = s.value));
+        } else {
+          let labels = s.labels.iter().map(|(k, v)| format!("{k}=\"{v}\"", v = escape_label_value(v))).collect::<Vec<String>>().join(",");
+          out.push_str(&format!("{sample_name}{{{labels}}} {val}\n", val = s.value));
+        }
+      }
+    }
+
+    if format == Format::OpenMetrics {
+      out.push_str("# EOF\n");
+    }
+
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn negotiate_format() {
+    assert_eq!(Format::negotiate(None), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("")), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("*/*")), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("application/json")), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("application/openmetrics-text; version=1.0.0")), Format::OpenMetrics);
+    // Prometheus scrape Accept header
+    assert_eq!(Format::negotiate(Some("application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1")), Format::OpenMetrics);
+  }
+
+  #[test]
+  fn negotiate_format_q_values() {
+    assert_eq!(Format::negotiate(Some("text/plain;version=0.0.4;q=0.9,application/openmetrics-text;version=1.0.0;q=0.1")), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("text/plain;q=0.5,application/openmetrics-text;q=0.5")), Format::OpenMetrics);
+    assert_eq!(Format::negotiate(Some("application/openmetrics-text; q=0")), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("application/openmetrics-text;q=0.0, text/plain")), Format::Prometheus);
+    assert_eq!(Format::negotiate(Some("Application/OpenMetrics-Text;Q=0.8, */*;q=0.2")), Format::OpenMetrics);
+    assert_eq!(Format::negotiate(Some("application/openmetrics-text;q=abc")), Format::Prometheus);
+  }
+
+  #[test]
+  fn sanitize_name_snake_cases() {
+    assert_eq!(sanitize_name("temperature"), "temperature");
+    assert_eq!(sanitize_name("thermostatOperatingState"), "thermostat_operating_state");
+    assert_eq!(sanitize_name("some-attr"), "some_attr");
+    assert_eq!(sanitize_name("hub_cpu_load_1min"), "hub_cpu_load_1_min");
+  }
+
+  #[test]
+  fn sanitize_name_invalid_characters() {
+    assert_eq!(sanitize_name("co2 (ppm)"), "co_2__ppm_");
+    assert_eq!(sanitize_name("température"), "temp_rature");
+    assert_eq!(sanitize_name("3dPrinter"), "_3_d_printer");
+    assert_eq!(sanitize_name(""), "_");
+  }
+
+  /// Renders the `raw` attributes added in order, one sample each valued with its position
+  fn render_names(raw: &[&str]) -> Vec<String> {
+    let mut m = Metrics::new("hubitat_");
+    for (i, r) in raw.iter().enumerate() {
+      let name = m.family_name(r);
+      m.add(&name, r, MetricType::Gauge, vec![], &i.to_string());
+    }
+    m.render(Format::Prometheus).lines().filter(|l| !l.starts_with('#')).map(|l| l.to_string()).collect()
+  }
+
+  #[test]
+  fn family_name_namespace() {
+    let mut m = Metrics::new("hubitat_");
+    assert_eq!(m.family_name("temperature"), "hubitat_temperature");
+    assert_eq!(Metrics::new("").family_name("switch"), "switch");
+    assert_eq!(render_names(&["thermostatMode", "thermostatMode"]), ["hubitat_thermostat_mode 0", "hubitat_thermostat_mode 1"]);
+  }
+
+  #[test]
+  fn family_name_collision_suffix() {
+    let alt = format!("hubitat_some_attr_{:08x}", fnv1a("some-attr"));
+    assert_eq!(render_names(&["some_attr", "some-attr"]), ["hubitat_some_attr 0".to_string(), format!("{alt} 1")]);
+    // The raw name that is already its sanitized name keeps it whatever the order the names are seen in
+    assert_eq!(render_names(&["some-attr", "some_attr"]), [format!("{alt} 0"), "hubitat_some_attr 1".to_string()]);
+    // Without the valid raw name the other one gets the sanitized name
+    assert_eq!(render_names(&["some-attr"]), ["hubitat_some_attr 0"]);
+  }
+
+  #[test]
+  fn family_name_collision_without_valid_name() {
+    let (dash, space) = (format!("hubitat_some_attr_{:08x}", fnv1a("some-attr")), format!("hubitat_some_attr_{:08x}", fnv1a("some attr")));
+    assert_eq!(render_names(&["some-attr", "some attr"]), [format!("{dash} 0"), format!("{space} 1")]);
+    assert_eq!(render_names(&["some attr", "some-attr"]), [format!("{space} 0"), format!("{dash} 1")]);
+  }
+
+  #[test]
+  fn unit_of_aliased_family() {
+    let mut m = Metrics::new("");
+    let name = m.family_name("someTemp_celsius");
+    m.add(&name, "Temp", MetricType::Gauge, vec![], "20");
+    m.set_unit(&name, "celsius");
+    assert_eq!(m.render(Format::OpenMetrics), "# HELP some_temp_celsius Temp\n# TYPE some_temp_celsius gauge\n# UNIT some_temp_celsius celsius\nsome_temp_celsius 20\n# EOF\n");
+  }
+
+  #[test]
+  fn fnv1a_is_stable() {
+    assert_eq!(fnv1a(""), 0x811c_9dc5);
+    assert_eq!(fnv1a("a"), 0xe40c_292c);
+  }
+
+  #[test]
+  fn const_labels_yield_to_sample_labels() {
+    let mut m = Metrics::new("");
+    m.set_const_labels(vec![("hub".to_string(), "main".to_string()), ("state".to_string(), "const".to_string())]);
+    m.add("mode", "Mode", MetricType::Gauge, vec![("state", "heat")], "1");
+    assert_eq!(m.render(Format::Prometheus), "# HELP mode Mode\n# TYPE mode gauge\nmode{hub=\"main\",state=\"heat\"} 1\n");
+  }
+
+  #[test]
+  fn render_escapes_label_values() {
+    let mut m = Metrics::new("");
+    m.add("energy_total", "Energy\nused", MetricType::Counter, vec![("device_label", "Kid's \"Nightlight\"\\")], "3");
+    assert_eq!(m.render(Format::OpenMetrics), "# HELP energy Energy\\nused\n# TYPE energy counter\nenergy_total{device_label=\"Kid's \\\"Nightlight\\\"\\\\\"} 3\n# EOF\n");
+  }
+}
@@ -0,0 +1,123 @@
+use std::{fs, sync::RwLock};
+
+use anyhow::{anyhow, Result};
+
+/// Credentials seen so far, every log line is scrubbed of them
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Query parameters whose value is always redacted from the logs
+const SECRET_PARAMS: &[&str] = &["access_token=", "password="];
+
+/// Returns the credential read from `file` when set, else `value`, and registers it for redaction.
+/// Files are read on every call, so a rotated secret is picked up.
+pub fn read(value: Option<&str>, file: Option<&str>) -> Result<Option<String>> {
+  let secret = match file {
+    Some(f) => Some(fs::read_to_string(f).map_err(|e| anyhow!("reading secret file {:?} failed: {:?}", f, e))?.trim_end_matches(['\r', '\n']).to_string()),
+    None => value.map(|v| v.to_string()),
+  };
+  if let Some(s) = &secret {
+    register(s);
+  }
+
+  Ok(secret)
+}
+
+/// Registers a credential to redact from the logs, along its form encoded variant as found in request bodies
+pub fn register(secret: &str) {
+  if secret.is_empty() {
+    return;
+  }
+
+  let mut secrets = SECRETS.write().unwrap();
+  for s in [secret.to_string(), url::form_urlencoded::byte_serialize(secret.as_bytes()).collect()] {
+    if !secrets.contains(&s) {
+      secrets.push(s);
+    }
+  }
+  // Longest first, so a secret containing another one is fully redacted
+  secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+/// Replaces the registered credentials and the values of the credential query parameters of `line`
+pub fn redact(line: &str) -> String {
+  let mut out = line.to_string();
+  for s in SECRETS.read().unwrap().iter() {
+    out = out.replace(s.as_str(), "<redacted>");
+  }
+
+  for param in SECRET_PARAMS {
+    let mut redacted = String::with_capacity(out.len());
+    let mut rest = out.as_str();
+    while let Some(idx) = rest.find(param) {
+      let (head, tail) = rest.split_at(idx + param.len());
+      redacted.push_str(head);
+      let end = tail.find(|c: char| c == '&' || c == '"' || c == '\'' || c == ')' || c == ',' || c.is_whitespace()).unwrap_or(tail.len());
+      if end > 0 {
+        redacted.push_str("<redacted>");
+      }
+      rest = &tail[end..];
+    }
+    redacted.push_str(rest);
+    out = redacted;
+  }
+
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The registered secrets are global, every test uses its own values
+
+  #[test]
+  fn registered_secret() {
+    register("s3cr3t-registered");
+    assert_eq!(redact("token s3cr3t-registered rejected"), "token <redacted> rejected");
+    assert_eq!(redact("nothing to hide"), "nothing to hide");
+  }
+
+  #[test]
+  fn form_encoded_variant() {
+    register("p@ss w0rd");
+    assert_eq!(redact("body: username=admin&pwd=p%40ss+w0rd&submit=Login"), "body: username=admin&pwd=<redacted>&submit=Login");
+    assert_eq!(redact("pwd \"p@ss w0rd\""), "pwd \"<redacted>\"");
+  }
+
+  #[test]
+  fn access_token_param() {
+    assert_eq!(redact("GET http://hub/apps/api/1/devices/all?access_token=abc-123&x=1 failed"), "GET http://hub/apps/api/1/devices/all?access_token=<redacted>&x=1 failed");
+    assert_eq!(redact("GET http://hub/apps/api/1/devices/all?access_token=abc-123"), "GET http://hub/apps/api/1/devices/all?access_token=<redacted>");
+    assert_eq!(redact("url: \"http://hub/?access_token=abc-123\", status: 401"), "url: \"http://hub/?access_token=<redacted>\", status: 401");
+    assert_eq!(redact("a?access_token=x&b?access_token=y"), "a?access_token=<redacted>&b?access_token=<redacted>");
+  }
+
+  #[test]
+  fn empty_param_value() {
+    assert_eq!(redact("GET http://hub/?access_token=&x=1"), "GET http://hub/?access_token=&x=1");
+    assert_eq!(redact("login with password="), "login with password=");
+  }
+
+  #[test]
+  fn password_param() {
+    assert_eq!(redact("POST /login username=admin&password=hunter2"), "POST /login username=admin&password=<redacted>");
+  }
+
+  #[test]
+  fn empty_secret_is_ignored() {
+    register("");
+    assert_eq!(redact("plain line"), "plain line");
+  }
+
+  #[test]
+  fn read_registers_the_secret() {
+    let path = std::env::temp_dir().join(format!("hubitat_exporter_secret_{}", std::process::id()));
+    fs::write(&path, "from-file-t0ken\n").unwrap();
+    let secret = read(Some("ignored-value"), path.to_str()).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(secret.as_deref(), Some("from-file-t0ken"));
+    assert_eq!(redact("token from-file-t0ken"), "token <redacted>");
+    assert_eq!(read(Some("inline-t0ken"), None).unwrap().as_deref(), Some("inline-t0ken"));
+    assert_eq!(read(None, None).unwrap(), None);
+  }
+}